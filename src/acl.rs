@@ -0,0 +1,79 @@
+//! Per-counter access control lists.
+//!
+//! A counter without an [`Acl`] is open to every caller. A counter with one
+//! limits each [`Permission`] it lists to the named principals; permissions it
+//! leaves out stay open. Callers without a principal, such as the stdio
+//! client, only get the open permissions.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rmcp::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::Principal;
+
+/// Something a caller may do to a counter.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// See the counter: read it, its windows and history, list it and
+    /// subscribe to it.
+    Read,
+    Increment,
+    Decrement,
+    /// Overwrite the value with `set`, `reset`, `compare_and_set`, `undo` or
+    /// `redo`.
+    Set,
+    Delete,
+}
+
+impl Permission {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Increment => "increment",
+            Self::Decrement => "decrement",
+            Self::Set => "set",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+/// The principals allowed each permission on a counter, as in
+/// `{ "set": ["ops"], "delete": [] }`. An omitted permission is granted to
+/// everyone, an empty list to no one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct Acl(pub BTreeMap<Permission, BTreeSet<String>>);
+
+impl Acl {
+    /// Whether `principal` has `permission`.
+    pub fn allows(&self, permission: Permission, principal: Option<&Principal>) -> bool {
+        self.0.get(&permission).is_none_or(|principals| {
+            principal.is_some_and(|principal| principals.contains(&principal.0))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn omitted_permissions_are_open() {
+        let acl = Acl(BTreeMap::from([
+            (Permission::Set, BTreeSet::from(["ops".to_string()])),
+            (Permission::Delete, BTreeSet::new()),
+        ]));
+        let ops = Principal("ops".to_string());
+        let ci = Principal("ci".to_string());
+        assert!(acl.allows(Permission::Set, Some(&ops)));
+        assert!(!acl.allows(Permission::Set, Some(&ci)));
+        assert!(!acl.allows(Permission::Set, None));
+        assert!(acl.allows(Permission::Increment, Some(&ci)));
+        assert!(acl.allows(Permission::Read, None));
+        assert!(!acl.allows(Permission::Delete, Some(&ops)));
+    }
+}
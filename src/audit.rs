@@ -0,0 +1,347 @@
+//! Audit trail of every tool call, written as JSON lines to a rotating set of
+//! files.
+//!
+//! `audit.jsonl` receives new entries. Once it would grow past the size limit
+//! it is renamed to `audit.jsonl.1`, older files shift up by one, and the
+//! oldest beyond the retention limit is deleted.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use rmcp::{
+    model::{Implementation, JsonObject},
+    schemars::{self, JsonSchema},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::namespace::DEFAULT_NAMESPACE;
+
+/// Name of the file receiving new entries.
+pub const AUDIT_FILE: &str = "audit.jsonl";
+
+/// Size past which the audit file is rotated unless configured otherwise.
+pub const DEFAULT_MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Rotated files kept unless configured otherwise.
+pub const DEFAULT_MAX_FILES: usize = 5;
+
+/// Client name and version sent on initialize.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ClientInfo {
+    pub name: String,
+    pub version: String,
+}
+
+impl From<&Implementation> for ClientInfo {
+    fn from(info: &Implementation) -> Self {
+        Self {
+            name: info.name.clone(),
+            version: info.version.clone(),
+        }
+    }
+}
+
+/// How a call ended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+    /// The tool ran; `is_error` is set when it reported a failure such as a
+    /// compare-and-set conflict.
+    Ok {
+        is_error: bool,
+        output: Option<Value>,
+    },
+    /// The call was rejected with a JSON-RPC error.
+    Error { code: i32, message: String },
+}
+
+/// Entries written before namespaces existed belong to the default one.
+fn default_namespace() -> String {
+    DEFAULT_NAMESPACE.to_string()
+}
+
+/// One tool call.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AuditEntry {
+    /// When the call finished.
+    pub timestamp: DateTime<Utc>,
+    /// MCP session that made the call.
+    pub session: u64,
+    /// Namespace the session was bound to.
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    pub client: Option<ClientInfo>,
+    /// Who the caller authenticated as, on an authenticated transport.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub principal: Option<String>,
+    /// JSON-RPC id of the `tools/call` request.
+    pub request_id: String,
+    pub tool: String,
+    pub arguments: Option<JsonObject>,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+    pub duration_ms: f64,
+}
+
+/// Criteria for [`AuditLog::query`]. Unset fields match every entry.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct AuditQuery {
+    /// Only calls to this tool.
+    pub tool: Option<String>,
+    /// Only calls whose 'name' argument is this counter.
+    pub counter: Option<String>,
+    /// Only calls made by this session.
+    pub session: Option<u64>,
+    /// Only calls from clients whose name contains this text.
+    pub client: Option<String>,
+    /// Only calls made by this authenticated principal.
+    pub principal: Option<String>,
+    /// Only calls made in this namespace. Set by the server to the caller's
+    /// own namespace.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub namespace: Option<String>,
+    /// Only calls at or after this time (RFC 3339).
+    pub since: Option<DateTime<Utc>>,
+    /// Only calls before this time (RFC 3339).
+    pub until: Option<DateTime<Utc>>,
+    /// Only failed calls, or only successful ones.
+    pub failed: Option<bool>,
+    /// Return at most this many of the most recent matches; defaults to 100.
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    const DEFAULT_LIMIT: usize = 100;
+
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        let failed = match &entry.outcome {
+            Outcome::Ok { is_error, .. } => *is_error,
+            Outcome::Error { .. } => true,
+        };
+        let counter = entry
+            .arguments
+            .as_ref()
+            .and_then(|args| args.get("name"))
+            .and_then(Value::as_str);
+        self.tool.as_ref().is_none_or(|tool| *tool == entry.tool)
+            && self
+                .counter
+                .as_deref()
+                .is_none_or(|name| counter == Some(name))
+            && self.session.is_none_or(|session| session == entry.session)
+            && self
+                .namespace
+                .as_ref()
+                .is_none_or(|namespace| *namespace == entry.namespace)
+            && self.client.as_deref().is_none_or(|client| {
+                entry
+                    .client
+                    .as_ref()
+                    .is_some_and(|info| info.name.contains(client))
+            })
+            && self
+                .principal
+                .as_ref()
+                .is_none_or(|principal| entry.principal.as_ref() == Some(principal))
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+            && self.failed.is_none_or(|f| f == failed)
+    }
+}
+
+struct Writer {
+    file: File,
+    len: u64,
+}
+
+/// Appends [`AuditEntry`] records to rotating files in one directory.
+#[derive(Clone)]
+pub struct AuditLog {
+    dir: PathBuf,
+    max_file_bytes: u64,
+    max_files: usize,
+    writer: Arc<Mutex<Writer>>,
+}
+
+impl AuditLog {
+    /// Opens or creates the audit file in `dir`, using the default rotation
+    /// limits.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(AUDIT_FILE))?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            dir,
+            max_file_bytes: DEFAULT_MAX_FILE_BYTES,
+            max_files: DEFAULT_MAX_FILES,
+            writer: Arc::new(Mutex::new(Writer { file, len })),
+        })
+    }
+
+    /// Rotates the audit file once it would grow past `bytes`.
+    pub fn max_file_bytes(mut self, bytes: u64) -> Self {
+        self.max_file_bytes = bytes;
+        self
+    }
+
+    /// Number of rotated files kept besides the current one.
+    pub fn max_files(mut self, files: usize) -> Self {
+        self.max_files = files;
+        self
+    }
+
+    fn path(&self, generation: usize) -> PathBuf {
+        match generation {
+            0 => self.dir.join(AUDIT_FILE),
+            n => self.dir.join(format!("{AUDIT_FILE}.{n}")),
+        }
+    }
+
+    /// Appends `entry`, rotating first if it would not fit.
+    pub fn record(&self, entry: &AuditEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut writer = self.writer.lock().expect("audit lock poisoned");
+        if writer.len > 0 && writer.len + line.len() as u64 > self.max_file_bytes {
+            self.rotate(&mut writer)?;
+        }
+        writer.file.write_all(&line)?;
+        writer.len += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&self, writer: &mut Writer) -> io::Result<()> {
+        remove_if_exists(&self.path(self.max_files))?;
+        for generation in (0..self.max_files).rev() {
+            let from = self.path(generation);
+            if from.exists() {
+                fs::rename(from, self.path(generation + 1))?;
+            }
+        }
+        writer.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(0))?;
+        writer.len = 0;
+        Ok(())
+    }
+
+    /// Returns the most recent entries matching `query`, oldest first.
+    pub fn query(&self, query: &AuditQuery) -> io::Result<Vec<AuditEntry>> {
+        let limit = query.limit.unwrap_or(AuditQuery::DEFAULT_LIMIT);
+        // Hold the writer so that a rotation cannot move files mid-read.
+        let _writer = self.writer.lock().expect("audit lock poisoned");
+        let mut entries = Vec::new();
+        for generation in (0..=self.max_files).rev() {
+            read_entries(&self.path(generation), query, &mut entries)?;
+        }
+        let skip = entries.len().saturating_sub(limit);
+        entries.drain(..skip);
+        Ok(entries)
+    }
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Appends the entries of the file at `path` that match `query`. Lines that do
+/// not parse, such as one torn by a crash, are skipped.
+fn read_entries(path: &Path, query: &AuditQuery, entries: &mut Vec<AuditEntry>) -> io::Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for line in BufReader::new(file).lines() {
+        if let Ok(entry) = serde_json::from_str::<AuditEntry>(&line?)
+            && query.matches(&entry)
+        {
+            entries.push(entry);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(tool: &str, name: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp: Utc::now(),
+            session: 1,
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            client: Some(ClientInfo {
+                name: "test-agent".to_string(),
+                version: "1.0".to_string(),
+            }),
+            principal: None,
+            request_id: "7".to_string(),
+            tool: tool.to_string(),
+            arguments: serde_json::json!({ "name": name }).as_object().cloned(),
+            outcome: Outcome::Ok {
+                is_error: false,
+                output: None,
+            },
+            duration_ms: 0.5,
+        }
+    }
+
+    #[test]
+    fn query_filters_and_keeps_the_latest() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog::open(dir.path()).unwrap();
+        for name in ["a", "b", "a", "a"] {
+            log.record(&entry("increment", name)).unwrap();
+        }
+        log.record(&entry("reset", "a")).unwrap();
+
+        let query = AuditQuery {
+            tool: Some("increment".to_string()),
+            counter: Some("a".to_string()),
+            client: Some("agent".to_string()),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let found = log.query(&query).unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|e| e.tool == "increment"));
+        assert_eq!(log.query(&AuditQuery::default()).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn rotation_keeps_a_bounded_number_of_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let line = serde_json::to_vec(&entry("increment", "a")).unwrap().len() as u64 + 1;
+        let log = AuditLog::open(dir.path())
+            .unwrap()
+            .max_file_bytes(line * 2)
+            .max_files(2);
+        for _ in 0..10 {
+            log.record(&entry("increment", "a")).unwrap();
+        }
+        let mut files: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["audit.jsonl", "audit.jsonl.1", "audit.jsonl.2"]);
+        // Two entries per file survive rotation.
+        assert_eq!(log.query(&AuditQuery::default()).unwrap().len(), 6);
+    }
+}
@@ -0,0 +1,171 @@
+//! Bearer-token authentication for the HTTP transports.
+//!
+//! Tokens are listed in a TOML file by the SHA-256 hash of each token, so the
+//! file never holds a usable secret:
+//!
+//! ```toml
+//! [tokens.ci-agent]
+//! sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//! ```
+//!
+//! Tokens are expected to be long random strings, for which a plain hash is
+//! as strong as a slow one. [`require_bearer`] checks the `Authorization`
+//! header of every request and stores the [`Principal`] it belongs to in the
+//! request's extensions. rmcp copies the request parts into the
+//! [`RequestContext`] of every MCP request, where [`principal`] finds it.
+
+use std::{collections::BTreeMap, collections::HashMap, fmt, io, path::Path, sync::Arc};
+
+use axum::{
+    extract::{Request, State},
+    http::{StatusCode, header, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rmcp::{RoleServer, service::RequestContext};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// The name a token was issued to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Principal(pub String);
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenFile {
+    #[serde(default)]
+    tokens: BTreeMap<String, TokenEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenEntry {
+    /// Lowercase hex SHA-256 of the token.
+    sha256: String,
+}
+
+/// Returns the lowercase hex SHA-256 of `token`, as stored in a token file.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// The principals allowed to connect, by the hash of their token.
+#[derive(Debug, Clone, Default)]
+pub struct Authenticator {
+    principals: Arc<HashMap<String, Principal>>,
+}
+
+impl Authenticator {
+    /// Accepts `(principal, token hash)` pairs.
+    pub fn new(hashes: impl IntoIterator<Item = (String, String)>) -> Self {
+        let principals = hashes
+            .into_iter()
+            .map(|(principal, hash)| (hash.to_ascii_lowercase(), Principal(principal)))
+            .collect();
+        Self {
+            principals: Arc::new(principals),
+        }
+    }
+
+    /// Reads a token file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let file: TokenFile = toml::from_str(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid token file {}: {e}", path.display()),
+            )
+        })?;
+        Ok(Self::new(
+            file.tokens
+                .into_iter()
+                .map(|(principal, entry)| (principal, entry.sha256)),
+        ))
+    }
+
+    /// Returns the principal `token` was issued to.
+    pub fn authenticate(&self, token: &str) -> Option<Principal> {
+        self.principals.get(&hash_token(token)).cloned()
+    }
+}
+
+/// Axum middleware rejecting requests without a valid bearer token with
+/// `401 Unauthorized`, and recording the [`Principal`] of the others.
+///
+/// ```no_run
+/// # fn wrap(router: axum::Router, auth: rust_counter_mcp::auth::Authenticator) -> axum::Router {
+/// use rust_counter_mcp::auth::require_bearer;
+///
+/// router.layer(axum::middleware::from_fn_with_state(auth, require_bearer))
+/// # }
+/// ```
+pub async fn require_bearer(
+    State(auth): State<Authenticator>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let principal = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| auth.authenticate(token.trim()));
+    match principal {
+        Some(principal) => {
+            request.extensions_mut().insert(principal);
+            next.run(request).await
+        }
+        None => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "missing or invalid bearer token",
+        )
+            .into_response(),
+    }
+}
+
+/// The principal that sent the HTTP request carrying this MCP request, if it
+/// went through [`require_bearer`].
+pub fn principal(context: &RequestContext<RoleServer>) -> Option<Principal> {
+    context
+        .extensions
+        .get::<Parts>()
+        .and_then(|parts| parts.extensions.get::<Principal>())
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_matched_by_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tokens.toml");
+        std::fs::write(
+            &path,
+            format!("[tokens.ci]\nsha256 = \"{}\"\n", hash_token("s3cret")),
+        )
+        .unwrap();
+        let auth = Authenticator::load(&path).unwrap();
+        assert_eq!(
+            auth.authenticate("s3cret"),
+            Some(Principal("ci".to_string()))
+        );
+        assert_eq!(auth.authenticate("guess"), None);
+        assert_eq!(
+            hash_token("test"),
+            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+        );
+    }
+}
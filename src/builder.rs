@@ -0,0 +1,253 @@
+use std::{collections::BTreeMap, io, sync::Arc, time::Duration};
+
+use crate::{
+    audit::AuditLog,
+    config::{Config, Limits, NamespaceConfig, PeerConfig},
+    counter::Counter,
+    crdt,
+    idempotency::DEFAULT_IDEMPOTENCY_TTL,
+    logging::ClientLogs,
+    namespace::{self, DEFAULT_NAMESPACE, Namespace, NamespaceParts},
+    rate_limit::RateLimits,
+    replication::{Replication, ReplicationConfig},
+    server::{CounterServer, DEFAULT_INSTRUCTIONS},
+    storage::Storage,
+};
+
+/// Number of log records after which the background task compacts the
+/// storage log into a fresh snapshot.
+pub const DEFAULT_COMPACT_THRESHOLD: usize = 1024;
+
+/// Configures and creates a [`CounterServer`].
+///
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// use rust_counter_mcp::{CounterServer, JsonFileStorage};
+///
+/// let server = CounterServer::builder()
+///     .storage(JsonFileStorage::open("./data")?)
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct CounterServerBuilder {
+    storage: Option<Arc<dyn Storage>>,
+    compact_threshold: usize,
+    counters: BTreeMap<String, Counter>,
+    limits: Limits,
+    namespaces: BTreeMap<String, NamespaceParts>,
+    pub(crate) rate_limits: RateLimits,
+    pub(crate) instructions: String,
+    pub(crate) audit: Option<AuditLog>,
+    idempotency_ttl: Duration,
+    pub(crate) client_logs: ClientLogs,
+    replica_id: Option<String>,
+    pub(crate) peers: BTreeMap<String, PeerConfig>,
+    replication: ReplicationConfig,
+}
+
+impl Default for CounterServerBuilder {
+    fn default() -> Self {
+        Self {
+            storage: None,
+            compact_threshold: DEFAULT_COMPACT_THRESHOLD,
+            counters: BTreeMap::new(),
+            limits: Limits::default(),
+            namespaces: BTreeMap::new(),
+            rate_limits: RateLimits::default(),
+            instructions: DEFAULT_INSTRUCTIONS.to_string(),
+            audit: None,
+            idempotency_ttl: DEFAULT_IDEMPOTENCY_TTL,
+            client_logs: ClientLogs::default(),
+            replica_id: None,
+            peers: BTreeMap::new(),
+            replication: ReplicationConfig::default(),
+        }
+    }
+}
+
+impl CounterServerBuilder {
+    /// Persists the counters of the default namespace in `storage`. Without
+    /// storage they live in memory only.
+    pub fn storage(mut self, storage: impl Storage) -> Self {
+        self.storage = Some(Arc::new(storage));
+        self
+    }
+
+    /// Records every tool call in `audit` and enables the `query_audit`
+    /// tool.
+    pub fn audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Sends clients that call `logging/setLevel` the events forwarded by
+    /// `logs`' [`ClientLogs::layer`], which the caller installs in its
+    /// `tracing` subscriber.
+    pub fn client_logs(mut self, logs: ClientLogs) -> Self {
+        self.client_logs = logs;
+        self
+    }
+
+    /// How long the result of a call made with an idempotency key is
+    /// remembered.
+    pub fn idempotency_ttl(mut self, ttl: Duration) -> Self {
+        self.idempotency_ttl = ttl;
+        self
+    }
+
+    /// Number of appended records after which the storage is compacted.
+    pub fn compact_threshold(mut self, records: usize) -> Self {
+        self.compact_threshold = records;
+        self
+    }
+
+    /// Creates `name` in the default namespace with the given state at
+    /// startup unless storage already holds a counter by that name.
+    pub fn counter(mut self, name: impl Into<String>, counter: Counter) -> Self {
+        self.counters.insert(name.into(), counter);
+        self
+    }
+
+    /// Limits the default namespace.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Hosts the namespace `name` with the settings in `config`, in addition
+    /// to the default one. Sessions bind to it by sending the
+    /// [`namespace::NAMESPACE_HEADER`] header with `initialize`.
+    pub fn namespace(mut self, name: impl Into<String>, config: &NamespaceConfig) -> Self {
+        let parts = self.namespaces.entry(name.into()).or_default();
+        parts.limits = config.limits;
+        parts.instructions = config.instructions.clone();
+        parts.principals = config.principals.clone();
+        for (name, counter) in &config.counters {
+            parts.counters.insert(name.clone(), counter.into());
+        }
+        self
+    }
+
+    /// Persists the counters of the namespace `name` in `storage`, hosting
+    /// the namespace with default settings unless [`Self::namespace`] adds
+    /// it.
+    pub fn namespace_storage(mut self, name: impl Into<String>, storage: impl Storage) -> Self {
+        self.namespaces.entry(name.into()).or_default().storage = Some(Arc::new(storage));
+        self
+    }
+
+    /// Limits how fast each session may call tools. Calls over the limit are
+    /// rejected with [`crate::error::RATE_LIMITED`].
+    pub fn rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    /// Identifies this instance in counters' replica state; see
+    /// [`crate::crdt`]. Instances that sync with each other need distinct
+    /// ids, and an instance should keep its id across restarts. A random id
+    /// is used when not set.
+    pub fn replica_id(mut self, id: impl Into<String>) -> Self {
+        self.replica_id = Some(id.into());
+        self
+    }
+
+    /// Lets the `sync` tool exchange counters with the instance described by
+    /// `config`, under `name`.
+    pub fn peer(mut self, name: impl Into<String>, config: PeerConfig) -> Self {
+        self.peers.insert(name.into(), config);
+        self
+    }
+
+    /// Streams the mutation log to followers, or follows a leader; see
+    /// [`crate::replication`].
+    pub fn replication(mut self, config: ReplicationConfig) -> Self {
+        self.replication = config;
+        self
+    }
+
+    /// Replaces the instructions sent to clients on initialize.
+    pub fn instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = instructions.into();
+        self
+    }
+
+    /// Applies the server settings from `config`: limits, rate limits,
+    /// initial counters, namespaces, instructions, the replica id, peers,
+    /// replication, the idempotency TTL and the compaction threshold.
+    /// Transport and storage are left to the caller.
+    pub fn config(mut self, config: &Config) -> Self {
+        self.limits = config.limits;
+        self.rate_limits = config.rate_limits.clone();
+        for (name, counter) in &config.counters {
+            self.counters.insert(name.clone(), counter.into());
+        }
+        if let Some(instructions) = &config.instructions {
+            self.instructions = instructions.clone();
+        }
+        if let Some(threshold) = config.storage.compact_threshold {
+            self.compact_threshold = threshold;
+        }
+        if let Some(secs) = config.idempotency.ttl_secs {
+            self.idempotency_ttl = Duration::from_secs(secs);
+        }
+        for (name, namespace) in &config.namespaces {
+            self = self.namespace(name.clone(), namespace);
+        }
+        if let Some(id) = &config.replica_id {
+            self.replica_id = Some(id.clone());
+        }
+        for (name, peer) in &config.peers {
+            self.peers.insert(name.clone(), peer.clone());
+        }
+        self.replication = config.replication.clone();
+        self
+    }
+
+    /// Loads the persisted counters of every namespace and, for those with
+    /// storage, spawns the background compaction task, then starts
+    /// replication. Must be called from within a Tokio runtime if storage or
+    /// replication is configured. Fails if a namespace name or the replica id
+    /// is invalid, an initial counter has inverted bounds or a value outside
+    /// them, the replication term file cannot be read or the replication
+    /// address cannot be bound.
+    pub fn build(mut self) -> io::Result<CounterServer> {
+        if self.namespaces.contains_key(DEFAULT_NAMESPACE) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the default namespace takes the server's own settings",
+            ));
+        }
+        let replica = self.replica_id.take().unwrap_or_else(crdt::new_replica_id);
+        crdt::validate_replica_id(&replica)?;
+        let default = NamespaceParts {
+            storage: self.storage.take(),
+            counters: std::mem::take(&mut self.counters),
+            limits: self.limits,
+            instructions: None,
+            principals: None,
+        };
+        let mut namespaces = BTreeMap::new();
+        let parts = std::mem::take(&mut self.namespaces);
+        for (name, parts) in std::iter::once((DEFAULT_NAMESPACE.to_string(), default)).chain(parts)
+        {
+            namespace::validate_name(&name)?;
+            let namespace = Arc::new(Namespace::load(
+                name.clone(),
+                parts,
+                self.compact_threshold,
+                self.idempotency_ttl,
+            )?);
+            namespace.start_compaction();
+            namespaces.insert(name, namespace);
+        }
+        let replication = Arc::new(Replication::new(
+            std::mem::take(&mut self.replication),
+            replica.clone(),
+        )?);
+        let server = CounterServer::from_parts(namespaces, replica, replication, self);
+        server.start_replication()?;
+        Ok(server)
+    }
+}
@@ -0,0 +1,426 @@
+//! Server settings read from a TOML file.
+//!
+//! ```toml
+//! transport = "http"
+//! bind = "127.0.0.1:8000"
+//! data_dir = "/var/lib/counters"
+//! log_level = "info"
+//! log_file = "/var/log/counter-mcp.log"
+//! replica_id = "laptop-1"
+//!
+//! [auth]
+//! token_file = "/etc/counter-mcp/tokens.toml"
+//!
+//! [limits]
+//! max_counters = 100
+//! history = 32
+//!
+//! [rate_limits]
+//! session = { burst = 20, per_minute = 600 }
+//! tools.increment = { burst = 5, per_minute = 60 }
+//!
+//! [storage]
+//! compact_threshold = 1024
+//!
+//! [idempotency]
+//! ttl_secs = 86400
+//!
+//! [metrics]
+//! bind = "127.0.0.1:9100"
+//!
+//! [audit]
+//! enabled = true
+//! max_file_bytes = 10485760
+//! max_files = 5
+//!
+//! [counters.seats]
+//! value = 100
+//! min = 0
+//! max = 100
+//! overflow = "clamp"
+//! quota = { max = 500, period_secs = 86400 }
+//! acl = { set = ["ops"], delete = ["ops"] }
+//!
+//! [counters.api_calls]
+//! kind = "rate"
+//!
+//! [namespaces.team-a]
+//! principals = ["team-a-agent"]
+//! limits = { max_counters = 10 }
+//!
+//! [namespaces.team-a.counters.builds]
+//! value = 0
+//!
+//! [peers.desktop]
+//! url = "http://192.168.1.20:8000/mcp"
+//! token = "..."
+//!
+//! [replication]
+//! role = "follower"
+//! bind = "127.0.0.1:7001"
+//! leaders = ["127.0.0.1:7000"]
+//! failover_secs = 10
+//! ```
+//!
+//! Every key is optional. The binary lets command-line flags and
+//! `COUNTER_MCP_*` environment variables override the file.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    acl::Acl,
+    audit::{DEFAULT_MAX_FILE_BYTES, DEFAULT_MAX_FILES},
+    counter::{Counter, CounterKind, OverflowPolicy, Quota},
+    rate_limit::RateLimits,
+    replication::ReplicationConfig,
+};
+
+/// Environment variable naming the config file to read.
+pub const CONFIG_ENV: &str = "COUNTER_MCP_CONFIG";
+
+/// How the binary talks to its clients.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportKind {
+    /// A single client on stdin/stdout.
+    #[default]
+    Stdio,
+    /// Streamable HTTP and legacy SSE on one listener.
+    Http,
+}
+
+impl FromStr for TransportKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stdio" => Ok(Self::Stdio),
+            "http" => Ok(Self::Http),
+            other => Err(format!(
+                "unknown transport '{other}', expected 'stdio' or 'http'"
+            )),
+        }
+    }
+}
+
+/// Caps on what clients may do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Largest number of counters that may exist at once, including the
+    /// default counter. Unlimited when absent.
+    pub max_counters: Option<usize>,
+    /// Operations kept per counter for `undo`; see
+    /// [`crate::history::DEFAULT_HISTORY_LIMIT`].
+    pub history: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// See [`crate::CounterServerBuilder::compact_threshold`].
+    pub compact_threshold: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Whether tool calls are written to the audit log in the data directory.
+    pub enabled: bool,
+    /// See [`crate::audit::AuditLog::max_file_bytes`].
+    pub max_file_bytes: u64,
+    /// See [`crate::audit::AuditLog::max_files`].
+    pub max_files: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_file_bytes: DEFAULT_MAX_FILE_BYTES,
+            max_files: DEFAULT_MAX_FILES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdempotencyConfig {
+    /// Seconds an idempotency key is remembered; see
+    /// [`crate::idempotency::DEFAULT_IDEMPOTENCY_TTL`].
+    pub ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Token file required of HTTP clients; see [`crate::auth`]. The HTTP
+    /// transport is open to anyone who can reach it when absent.
+    pub token_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address to serve `GET /metrics` on. Disabled when absent.
+    pub bind: Option<SocketAddr>,
+}
+
+/// A counter created at startup unless storage already holds one by that
+/// name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CounterConfig {
+    pub kind: CounterKind,
+    pub value: i64,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub overflow: OverflowPolicy,
+    pub quota: Option<Quota>,
+    pub acl: Option<Acl>,
+}
+
+impl From<&CounterConfig> for Counter {
+    fn from(config: &CounterConfig) -> Self {
+        Self {
+            value: config.value,
+            min: config.min,
+            max: config.max,
+            quota: config.quota,
+            acl: config.acl.clone(),
+            ..Self::of_kind(config.kind, config.overflow)
+        }
+    }
+}
+
+/// Another instance that the `sync` tool exchanges counters with; see
+/// [`crate::crdt`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerConfig {
+    /// Streamable HTTP endpoint of the peer, such as
+    /// `http://host:8000/mcp`.
+    pub url: String,
+    /// Bearer token presented to the peer, if it requires one.
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Namespace to sync with on the peer. Defaults to the namespace of the
+    /// session calling `sync`.
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+/// A namespace hosted besides the default one; see [`crate::namespace`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NamespaceConfig {
+    /// Principals allowed to bind sessions to the namespace. Anyone may when
+    /// absent.
+    pub principals: Option<BTreeSet<String>>,
+    /// Replaces the instructions sent to clients in the namespace.
+    pub instructions: Option<String>,
+    pub limits: Limits,
+    pub counters: BTreeMap<String, CounterConfig>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub transport: Option<TransportKind>,
+    /// Address the HTTP transport listens on.
+    pub bind: Option<SocketAddr>,
+    /// Directory holding the snapshot and log; see
+    /// [`crate::JsonFileStorage::default_dir`] for the fallback.
+    pub data_dir: Option<PathBuf>,
+    /// A `tracing` filter such as `info` or `rust_counter_mcp=debug`.
+    pub log_level: Option<String>,
+    /// File to append logs to instead of stderr.
+    pub log_file: Option<PathBuf>,
+    /// Replaces the instructions sent to clients on initialize.
+    pub instructions: Option<String>,
+    /// Identifies this instance in counters' replica state. Generated and
+    /// kept in the data directory when absent.
+    pub replica_id: Option<String>,
+    pub auth: AuthConfig,
+    pub limits: Limits,
+    pub rate_limits: RateLimits,
+    pub storage: StorageConfig,
+    pub audit: AuditConfig,
+    pub idempotency: IdempotencyConfig,
+    pub metrics: MetricsConfig,
+    /// Counters of the default namespace.
+    pub counters: BTreeMap<String, CounterConfig>,
+    pub namespaces: BTreeMap<String, NamespaceConfig>,
+    pub peers: BTreeMap<String, PeerConfig>,
+    pub replication: ReplicationConfig,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Reads and parses the file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid config file {}: {e}", path.display()),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{acl::Permission, replication::Role};
+
+    #[test]
+    fn empty_file_is_all_defaults() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn parses_every_section() {
+        let config = Config::parse(
+            r#"
+            transport = "http"
+            bind = "0.0.0.0:9000"
+            data_dir = "/tmp/counters"
+            log_level = "debug"
+            log_file = "/tmp/counters.log"
+            instructions = "Count things."
+            replica_id = "laptop"
+
+            [auth]
+            token_file = "/tmp/tokens.toml"
+
+            [limits]
+            max_counters = 3
+            history = 5
+
+            [rate_limits]
+            session = { burst = 10, per_minute = 60 }
+            tools.increment = { burst = 2, per_minute = 6 }
+
+            [storage]
+            compact_threshold = 10
+
+            [audit]
+            enabled = false
+
+            [idempotency]
+            ttl_secs = 60
+
+            [metrics]
+            bind = "127.0.0.1:9100"
+
+            [counters.tickets]
+            value = 5
+            min = 0
+            max = 9
+            overflow = "wrap"
+            quota = { max = 100, period_secs = 3600 }
+            acl = { set = ["ops"], delete = [] }
+
+            [counters.calls]
+            kind = "rate"
+
+            [namespaces.team-a]
+            principals = ["ci"]
+            instructions = "Count team A's things."
+            limits = { max_counters = 2 }
+
+            [namespaces.team-a.counters.builds]
+            value = 4
+
+            [peers.desktop]
+            url = "http://desktop:8000/mcp"
+            token = "secret"
+
+            [replication]
+            role = "follower"
+            bind = "127.0.0.1:7001"
+            leaders = ["127.0.0.1:7000"]
+            failover_secs = 5
+            term_file = "/tmp/replication_term"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.transport, Some(TransportKind::Http));
+        assert_eq!(config.bind, Some("0.0.0.0:9000".parse().unwrap()));
+        assert_eq!(config.data_dir, Some(PathBuf::from("/tmp/counters")));
+        assert_eq!(config.log_level.as_deref(), Some("debug"));
+        assert_eq!(config.log_file, Some(PathBuf::from("/tmp/counters.log")));
+        assert_eq!(
+            config.auth.token_file,
+            Some(PathBuf::from("/tmp/tokens.toml"))
+        );
+        assert_eq!(config.limits.max_counters, Some(3));
+        assert_eq!(config.limits.history, Some(5));
+        assert_eq!(config.rate_limits.session.map(|l| l.burst), Some(10));
+        assert_eq!(config.rate_limits.tools["increment"].per_minute, 6);
+        assert_eq!(config.storage.compact_threshold, Some(10));
+        assert!(!config.audit.enabled);
+        assert_eq!(config.idempotency.ttl_secs, Some(60));
+        assert_eq!(config.metrics.bind, Some("127.0.0.1:9100".parse().unwrap()));
+        assert_eq!(config.audit.max_files, DEFAULT_MAX_FILES);
+        let tickets = Counter::from(&config.counters["tickets"]);
+        assert_eq!(tickets.value, 5);
+        assert_eq!(tickets.version, 0);
+        assert_eq!((tickets.min, tickets.max), (Some(0), Some(9)));
+        assert_eq!(tickets.overflow, OverflowPolicy::Wrap);
+        assert_eq!(tickets.quota.map(|q| q.max), Some(100));
+        assert_eq!(tickets.kind(), CounterKind::Standard);
+        let acl = tickets.acl.unwrap();
+        assert_eq!(
+            acl.0.get(&Permission::Set),
+            Some(&BTreeSet::from(["ops".to_string()]))
+        );
+        assert_eq!(acl.0.get(&Permission::Delete), Some(&BTreeSet::new()));
+        assert_eq!(acl.0.get(&Permission::Read), None);
+        assert_eq!(
+            Counter::from(&config.counters["calls"]).kind(),
+            CounterKind::Rate
+        );
+        let team_a = &config.namespaces["team-a"];
+        assert_eq!(team_a.principals, Some(BTreeSet::from(["ci".to_string()])));
+        assert_eq!(team_a.limits.max_counters, Some(2));
+        assert_eq!(team_a.counters["builds"].value, 4);
+        assert_eq!(config.replica_id.as_deref(), Some("laptop"));
+        assert_eq!(config.peers["desktop"].url, "http://desktop:8000/mcp");
+        assert_eq!(config.peers["desktop"].token.as_deref(), Some("secret"));
+        assert_eq!(config.peers["desktop"].namespace, None);
+        assert_eq!(config.replication.role, Role::Follower);
+        assert_eq!(
+            config.replication.bind,
+            Some("127.0.0.1:7001".parse().unwrap())
+        );
+        assert_eq!(
+            config.replication.leaders,
+            ["127.0.0.1:7000".parse::<SocketAddr>().unwrap()]
+        );
+        assert_eq!(config.replication.failover_secs, Some(5));
+        assert_eq!(
+            config.replication.term_file,
+            Some(PathBuf::from("/tmp/replication_term"))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(Config::parse("[limits]\nmax_countres = 3").is_err());
+        assert!(Config::parse("transport = \"carrier-pigeon\"").is_err());
+    }
+}
@@ -0,0 +1,429 @@
+use chrono::{DateTime, Utc};
+use rmcp::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::{acl::Acl, crdt::PnCounter, rate::RateWindows};
+
+/// What happens when a step would move a counter past its `min` or `max`, or
+/// past `i64::MIN` or `i64::MAX` for a counter without bounds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Reject the call and leave the counter unchanged.
+    #[default]
+    Error,
+    /// Stop at the nearest bound.
+    #[serde(alias = "clamp")]
+    Saturate,
+    /// Wrap around to the other bound, like two's complement arithmetic does
+    /// for an unbounded counter.
+    Wrap,
+}
+
+/// What a counter tracks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CounterKind {
+    /// A value that can be stepped in either direction or set.
+    #[default]
+    Standard,
+    /// Events over sliding windows; see [`crate::rate`]. Only `increment`
+    /// and `reset` apply, and `value` is the total number of events.
+    Rate,
+}
+
+/// Caps the total amount a counter may be raised by in each period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Quota {
+    /// Largest total amount the counter may rise by per period.
+    pub max: u64,
+    /// Length of a period in seconds. Periods are aligned to the Unix epoch,
+    /// so a period of 3600 resets on the hour.
+    pub period_secs: u64,
+}
+
+/// Increments counted against a [`Quota`] in its current period.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    /// Start of the period, in Unix seconds.
+    pub period_start: i64,
+    pub used: u64,
+}
+
+/// A single named tally.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "CounterRepr")]
+pub struct Counter {
+    pub value: i64,
+    /// Number of writes the counter has accepted since it was created.
+    pub version: u64,
+    pub overflow: OverflowPolicy,
+    /// Lowest value the counter may hold.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<i64>,
+    /// Highest value the counter may hold.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<i64>,
+    /// Recent events, present only on a rate counter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<RateWindows>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<Quota>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_usage: Option<QuotaUsage>,
+    /// Who may access the counter; open to everyone when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acl: Option<Acl>,
+    /// What each replica added and subtracted; see [`crate::crdt`]. Always
+    /// sums to `value` once a write is committed.
+    #[serde(default, skip_serializing_if = "PnCounter::is_empty")]
+    pub replicas: PnCounter,
+}
+
+/// Snapshots written before counters carried settings stored them as a bare
+/// integer.
+// Only ever held briefly while deserializing, so its size does not matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CounterRepr {
+    Value(i64),
+    Full {
+        value: i64,
+        #[serde(default)]
+        version: u64,
+        #[serde(default)]
+        overflow: OverflowPolicy,
+        #[serde(default)]
+        min: Option<i64>,
+        #[serde(default)]
+        max: Option<i64>,
+        #[serde(default)]
+        rate: Option<RateWindows>,
+        #[serde(default)]
+        quota: Option<Quota>,
+        #[serde(default)]
+        quota_usage: Option<QuotaUsage>,
+        #[serde(default)]
+        acl: Option<Acl>,
+        #[serde(default)]
+        replicas: PnCounter,
+    },
+}
+
+impl From<CounterRepr> for Counter {
+    fn from(repr: CounterRepr) -> Self {
+        match repr {
+            CounterRepr::Value(value) => Self {
+                value,
+                ..Self::default()
+            },
+            CounterRepr::Full {
+                value,
+                version,
+                overflow,
+                min,
+                max,
+                rate,
+                quota,
+                quota_usage,
+                acl,
+                replicas,
+            } => Self {
+                value,
+                version,
+                overflow,
+                min,
+                max,
+                rate,
+                quota,
+                quota_usage,
+                acl,
+                replicas,
+            },
+        }
+    }
+}
+
+impl Counter {
+    pub fn new(overflow: OverflowPolicy) -> Self {
+        Self {
+            overflow,
+            ..Self::default()
+        }
+    }
+
+    /// Creates an empty counter of `kind`.
+    pub fn of_kind(kind: CounterKind, overflow: OverflowPolicy) -> Self {
+        Self {
+            rate: (kind == CounterKind::Rate).then(RateWindows::default),
+            ..Self::new(overflow)
+        }
+    }
+
+    pub fn kind(&self) -> CounterKind {
+        match self.rate {
+            Some(_) => CounterKind::Rate,
+            None => CounterKind::Standard,
+        }
+    }
+
+    /// Whether the counter has a `min` or `max`.
+    pub fn is_bounded(&self) -> bool {
+        self.min.is_some() || self.max.is_some()
+    }
+
+    /// Whether `value` lies within the counter's bounds.
+    pub fn contains(&self, value: i64) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+
+    /// Checks that the bounds are ordered and hold the current value, that a
+    /// rate counter has none, and that a quota has a period.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.quota.is_some_and(|quota| quota.period_secs == 0) {
+            return Err("a quota period must be at least one second".to_string());
+        }
+        if self.rate.is_some() && self.is_bounded() {
+            return Err("rate counters cannot have a min or max".to_string());
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max)
+            && min > max
+        {
+            return Err(format!("min ({min}) is greater than max ({max})"));
+        }
+        if !self.contains(self.value) {
+            return Err(format!("value {} is outside its bounds", self.value));
+        }
+        Ok(())
+    }
+
+    /// Start of the quota period containing `now`, in Unix seconds.
+    fn quota_period(quota: Quota, now: DateTime<Utc>) -> i64 {
+        let period = i64::try_from(quota.period_secs).unwrap_or(i64::MAX);
+        let now = now.timestamp();
+        now - now.rem_euclid(period)
+    }
+
+    /// Amount counted against the quota in the period containing `now`.
+    pub fn quota_used(&self, now: DateTime<Utc>) -> Option<u64> {
+        let quota = self.quota?;
+        let start = Self::quota_period(quota, now);
+        Some(
+            self.quota_usage
+                .filter(|usage| usage.period_start == start)
+                .map_or(0, |usage| usage.used),
+        )
+    }
+
+    /// Counts an increment of `amount` made at `now` against the quota.
+    /// Returns when the current period ends, leaving the usage untouched, if
+    /// the increment would exceed the quota.
+    pub fn charge_quota(&mut self, amount: u64, now: DateTime<Utc>) -> Result<(), DateTime<Utc>> {
+        let Some(quota) = self.quota else {
+            return Ok(());
+        };
+        let start = Self::quota_period(quota, now);
+        let used = self.quota_used(now).unwrap_or(0);
+        if used.saturating_add(amount) > quota.max {
+            let end = start.saturating_add_unsigned(quota.period_secs);
+            return Err(DateTime::from_timestamp(end, 0).unwrap_or(DateTime::<Utc>::MAX_UTC));
+        }
+        self.quota_usage = Some(QuotaUsage {
+            period_start: start,
+            used: used + amount,
+        });
+        Ok(())
+    }
+
+    /// Credits `replica` with whatever part of the value its replica state
+    /// does not account for yet.
+    pub fn reconcile(&mut self, replica: &str) {
+        let delta = self.value.wrapping_sub(self.replicas.value());
+        if delta != 0 {
+            self.replicas.add(replica, delta);
+        }
+    }
+
+    /// Takes in the changes recorded in `replicas`, bringing their sum
+    /// within the counter's bounds according to the overflow policy. Returns
+    /// whether the counter changed, or the sum, leaving the counter
+    /// untouched, if the policy is [`OverflowPolicy::Error`] and the sum
+    /// lies outside the bounds.
+    ///
+    /// A value clamped or wrapped here no longer matches the replica state;
+    /// [`Counter::reconcile`] records the difference as a local change.
+    pub fn merge(&mut self, replicas: &PnCounter) -> Result<bool, i64> {
+        let mut merged = self.replicas.clone();
+        merged.merge(replicas);
+        if merged == self.replicas {
+            return Ok(false);
+        }
+        let sum = merged.value();
+        self.value = self.bounded(i128::from(sum)).ok_or(sum)?;
+        self.replicas = merged;
+        self.version += 1;
+        Ok(true)
+    }
+
+    pub fn set(&mut self, value: i64) {
+        self.value = value;
+        self.version += 1;
+    }
+
+    /// Adds `amount` according to the overflow policy. Returns `None`, leaving
+    /// the counter untouched, if the policy is [`OverflowPolicy::Error`] and
+    /// the result would leave the counter's bounds.
+    pub fn increment(&mut self, amount: i64) -> Option<i64> {
+        self.step(i128::from(amount))
+    }
+
+    /// Subtracts `amount` according to the overflow policy, like
+    /// [`Counter::increment`].
+    pub fn decrement(&mut self, amount: i64) -> Option<i64> {
+        self.step(-i128::from(amount))
+    }
+
+    fn step(&mut self, delta: i128) -> Option<i64> {
+        self.value = self.bounded(i128::from(self.value) + delta)?;
+        self.version += 1;
+        Some(self.value)
+    }
+
+    /// Brings `next` within the counter's bounds according to the overflow
+    /// policy, or returns `None` if the policy is [`OverflowPolicy::Error`]
+    /// and it lies outside them.
+    fn bounded(&self, next: i128) -> Option<i64> {
+        let min = i128::from(self.min.unwrap_or(i64::MIN));
+        let max = i128::from(self.max.unwrap_or(i64::MAX));
+        let next = if (min..=max).contains(&next) {
+            next
+        } else {
+            match self.overflow {
+                OverflowPolicy::Error => return None,
+                OverflowPolicy::Saturate => next.clamp(min, max),
+                OverflowPolicy::Wrap => min + (next - min).rem_euclid(max - min + 1),
+            }
+        };
+        Some(i64::try_from(next).expect("bounded value lies within i64 bounds"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounded(value: i64, min: i64, max: i64, overflow: OverflowPolicy) -> Counter {
+        Counter {
+            value,
+            min: Some(min),
+            max: Some(max),
+            ..Counter::new(overflow)
+        }
+    }
+
+    #[test]
+    fn unbounded_counters_use_the_range_of_i64() {
+        let mut counter = Counter::new(OverflowPolicy::Wrap);
+        counter.set(i64::MAX);
+        assert_eq!(counter.increment(2), Some(i64::MIN + 1));
+        let mut counter = Counter::new(OverflowPolicy::Saturate);
+        assert_eq!(counter.decrement(i64::MIN), Some(i64::MAX));
+        let mut counter = Counter::new(OverflowPolicy::Error);
+        counter.set(i64::MIN);
+        assert_eq!(counter.decrement(1), None);
+        assert_eq!(counter.version, 1);
+    }
+
+    #[test]
+    fn bounded_counters_stay_within_bounds() {
+        let mut seats = bounded(1, 0, 10, OverflowPolicy::Error);
+        assert_eq!(seats.decrement(2), None);
+        assert_eq!(seats.value, 1);
+        assert_eq!(seats.decrement(1), Some(0));
+
+        let mut clamped = bounded(8, 0, 10, OverflowPolicy::Saturate);
+        assert_eq!(clamped.increment(5), Some(10));
+        assert_eq!(clamped.decrement(i64::MAX), Some(0));
+
+        let mut wrapped = bounded(8, 0, 9, OverflowPolicy::Wrap);
+        assert_eq!(wrapped.increment(5), Some(3));
+        assert_eq!(wrapped.decrement(4), Some(9));
+    }
+
+    #[test]
+    fn clamp_is_an_alias_for_saturate() {
+        let policy: OverflowPolicy = serde_json::from_str("\"clamp\"").unwrap();
+        assert_eq!(policy, OverflowPolicy::Saturate);
+    }
+
+    #[test]
+    fn quotas_reset_each_period() {
+        let mut counter = Counter {
+            quota: Some(Quota {
+                max: 5,
+                period_secs: 60,
+            }),
+            ..Counter::default()
+        };
+        let at = |seconds: i64| DateTime::from_timestamp(1_699_920_000 + seconds, 0).unwrap();
+        assert_eq!(counter.charge_quota(3, at(10)), Ok(()));
+        assert_eq!(counter.charge_quota(3, at(20)), Err(at(60)));
+        assert_eq!(counter.charge_quota(2, at(30)), Ok(()));
+        assert_eq!(counter.quota_used(at(59)), Some(5));
+        assert_eq!(counter.quota_used(at(60)), Some(0));
+        assert_eq!(counter.charge_quota(5, at(61)), Ok(()));
+    }
+
+    #[test]
+    fn merging_takes_in_the_changes_of_other_replicas() {
+        let mut local = Counter::default();
+        local.increment(3);
+        local.reconcile("a");
+        let mut remote = Counter::default();
+        remote.decrement(1);
+        remote.reconcile("b");
+
+        assert_eq!(local.merge(&remote.replicas), Ok(true));
+        assert_eq!((local.value, local.version), (2, 2));
+        assert_eq!(local.merge(&remote.replicas), Ok(false));
+        assert_eq!(local.version, 2);
+        assert_eq!(remote.merge(&local.replicas), Ok(true));
+        assert_eq!(remote.value, 2);
+    }
+
+    #[test]
+    fn merging_keeps_counters_within_bounds() {
+        let mut remote = Counter::default();
+        remote.increment(5);
+        remote.reconcile("b");
+
+        let mut strict = bounded(8, 0, 10, OverflowPolicy::Error);
+        strict.reconcile("a");
+        assert_eq!(strict.merge(&remote.replicas), Err(13));
+        assert_eq!((strict.value, strict.version), (8, 0));
+
+        let mut clamped = bounded(8, 0, 10, OverflowPolicy::Saturate);
+        clamped.reconcile("a");
+        assert_eq!(clamped.merge(&remote.replicas), Ok(true));
+        assert_eq!(clamped.value, 10);
+        // The clamp becomes a change of the local replica, which peers merge
+        // in turn.
+        clamped.reconcile("a");
+        assert_eq!(clamped.replicas.value(), 10);
+        assert_eq!(remote.merge(&clamped.replicas), Ok(true));
+        assert_eq!(remote.value, 10);
+    }
+
+    #[test]
+    fn validate_rejects_inverted_bounds_and_stray_values() {
+        assert!(bounded(5, 10, 0, OverflowPolicy::Error).validate().is_err());
+        assert!(
+            bounded(11, 0, 10, OverflowPolicy::Error)
+                .validate()
+                .is_err()
+        );
+        assert!(bounded(10, 0, 10, OverflowPolicy::Error).validate().is_ok());
+    }
+}
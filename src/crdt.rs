@@ -0,0 +1,232 @@
+//! PN-Counter CRDT state, so that server instances converge without a
+//! coordinator.
+//!
+//! Every counter keeps, for each replica that ever changed it, the total that
+//! replica added and the total it subtracted. A replica only grows its own
+//! entries, and merging two states takes the larger total of each entry, so
+//! merges are commutative, associative and idempotent. The value of a counter
+//! is the sum of every addition minus the sum of every subtraction.
+//!
+//! Tools keep working on plain values: after each call the server records
+//! the difference between a counter's new value and its replica state as a
+//! change made by the local replica. Only that state travels between
+//! instances; bounds, quotas and rate windows are enforced locally.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rmcp::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    acl::Acl,
+    counter::{Counter, OverflowPolicy, Quota},
+};
+
+/// File in the data directory holding the replica id of the instance.
+pub const REPLICA_ID_FILE: &str = "replica_id";
+
+/// Replica credited with the value a counter starts with, whether from the
+/// config file, `create_counter` or a snapshot written before counters
+/// carried replica state. Instances seeded with the same counter then agree
+/// on its starting value instead of adding it up.
+pub const INITIAL_REPLICA: &str = "initial";
+
+/// Per-replica totals of one counter.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct PnCounter {
+    /// Total added by each replica.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub increments: BTreeMap<String, u64>,
+    /// Total subtracted by each replica.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub decrements: BTreeMap<String, u64>,
+}
+
+impl PnCounter {
+    pub fn is_empty(&self) -> bool {
+        self.increments.is_empty() && self.decrements.is_empty()
+    }
+
+    /// The counter's value. Totals past the range of `i64` wrap around, as
+    /// the value of a counter with the wrap overflow policy does.
+    pub fn value(&self) -> i64 {
+        let added: i128 = self.increments.values().copied().map(i128::from).sum();
+        let subtracted: i128 = self.decrements.values().copied().map(i128::from).sum();
+        (added - subtracted) as i64
+    }
+
+    /// Records that `replica` moved the counter by `delta`.
+    pub fn add(&mut self, replica: &str, delta: i64) {
+        let totals = if delta >= 0 {
+            &mut self.increments
+        } else {
+            &mut self.decrements
+        };
+        let total = totals.entry(replica.to_string()).or_default();
+        *total = total.saturating_add(delta.unsigned_abs());
+    }
+
+    /// Takes in every change `other` has seen.
+    pub fn merge(&mut self, other: &PnCounter) {
+        for (mine, theirs) in [
+            (&mut self.increments, &other.increments),
+            (&mut self.decrements, &other.decrements),
+        ] {
+            for (replica, total) in theirs {
+                let entry = mine.entry(replica.clone()).or_default();
+                *entry = (*entry).max(*total);
+            }
+        }
+    }
+}
+
+/// A counter as exchanged with peers: its replica state, and the settings
+/// used to create it on a peer that does not hold it yet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReplicatedCounter {
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub quota: Option<Quota>,
+    pub acl: Option<Acl>,
+    pub replicas: PnCounter,
+}
+
+impl From<&Counter> for ReplicatedCounter {
+    fn from(counter: &Counter) -> Self {
+        Self {
+            overflow: counter.overflow,
+            min: counter.min,
+            max: counter.max,
+            quota: counter.quota,
+            acl: counter.acl.clone(),
+            replicas: counter.replicas.clone(),
+        }
+    }
+}
+
+impl ReplicatedCounter {
+    /// A new local counter holding this state.
+    pub fn to_counter(&self) -> Counter {
+        Counter {
+            value: self.replicas.value(),
+            min: self.min,
+            max: self.max,
+            quota: self.quota,
+            acl: self.acl.clone(),
+            replicas: self.replicas.clone(),
+            ..Counter::new(self.overflow)
+        }
+    }
+}
+
+/// Checks that `id` can name the local replica.
+pub fn validate_replica_id(id: &str) -> io::Result<()> {
+    if id.is_empty() || id == INITIAL_REPLICA {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid replica id '{id}'"),
+        ));
+    }
+    Ok(())
+}
+
+/// Makes up a replica id that is unique in practice.
+pub fn new_replica_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let seed = format!("{nanos}-{}", std::process::id());
+    let digest = Sha256::digest(seed.as_bytes());
+    digest[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Reads the replica id stored in `dir`, creating one on first use so that
+/// the instance keeps its id across restarts.
+pub fn load_or_create_replica_id(dir: &Path) -> io::Result<String> {
+    let path = dir.join(REPLICA_ID_FILE);
+    match fs::read_to_string(&path) {
+        Ok(id) if !id.trim().is_empty() => return Ok(id.trim().to_string()),
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    fs::create_dir_all(dir)?;
+    let id = new_replica_id();
+    fs::write(&path, format!("{id}\n"))?;
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn pn_counter() -> impl Strategy<Value = PnCounter> {
+        let totals = || prop::collection::btree_map("[a-d]", 0..1_000_000u64, 0..4);
+        (totals(), totals()).prop_map(|(increments, decrements)| PnCounter {
+            increments,
+            decrements,
+        })
+    }
+
+    fn merged(a: &PnCounter, b: &PnCounter) -> PnCounter {
+        let mut merged = a.clone();
+        merged.merge(b);
+        merged
+    }
+
+    proptest! {
+        #[test]
+        fn merge_is_commutative(a in pn_counter(), b in pn_counter()) {
+            prop_assert_eq!(merged(&a, &b), merged(&b, &a));
+        }
+
+        #[test]
+        fn merge_is_idempotent(a in pn_counter(), b in pn_counter()) {
+            prop_assert_eq!(merged(&a, &a), a.clone());
+            let once = merged(&a, &b);
+            prop_assert_eq!(merged(&once, &b), once);
+        }
+
+        #[test]
+        fn merge_is_associative(a in pn_counter(), b in pn_counter(), c in pn_counter()) {
+            prop_assert_eq!(merged(&merged(&a, &b), &c), merged(&a, &merged(&b, &c)));
+        }
+
+        #[test]
+        fn replicas_converge_on_the_sum_of_their_changes(
+            a in prop::collection::vec(-1000..1000i64, 0..20),
+            b in prop::collection::vec(-1000..1000i64, 0..20),
+        ) {
+            let mut left = PnCounter::default();
+            a.iter().for_each(|delta| left.add("left", *delta));
+            let mut right = PnCounter::default();
+            b.iter().for_each(|delta| right.add("right", *delta));
+            let expected: i64 = a.iter().chain(&b).sum();
+            prop_assert_eq!(merged(&left, &right).value(), expected);
+            prop_assert_eq!(merged(&right, &left).value(), expected);
+        }
+    }
+
+    #[test]
+    fn replica_ids_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = load_or_create_replica_id(dir.path()).unwrap();
+        assert_eq!(id.len(), 16);
+        assert_eq!(load_or_create_replica_id(dir.path()).unwrap(), id);
+    }
+}
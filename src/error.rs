@@ -0,0 +1,263 @@
+//! Errors returned by the counter tools.
+//!
+//! Codes in the JSON-RPC implementation-defined range (-32000 to -32099) are
+//! used for failures specific to this server so that clients can tell them
+//! apart from protocol errors.
+
+use chrono::{DateTime, Utc};
+use rmcp::{ErrorData, model::ErrorCode};
+use serde_json::json;
+
+use crate::{
+    acl::Permission,
+    counter::{Counter, Quota},
+    rate_limit::Exceeded,
+    server::DEFAULT_COUNTER,
+};
+
+/// A step would move a counter outside the range of `i64`.
+pub const COUNTER_OVERFLOW: ErrorCode = ErrorCode(-32010);
+
+/// Creating a counter would exceed the configured `max_counters`.
+pub const COUNTER_LIMIT: ErrorCode = ErrorCode(-32011);
+
+/// A write would move a counter past its `min` or `max`.
+pub const COUNTER_OUT_OF_BOUNDS: ErrorCode = ErrorCode(-32012);
+
+/// The session called tools faster than its rate limit allows.
+pub const RATE_LIMITED: ErrorCode = ErrorCode(-32013);
+
+/// An increment would exceed the counter's quota for the current period.
+pub const QUOTA_EXCEEDED: ErrorCode = ErrorCode(-32014);
+
+/// The counter's ACL does not grant the caller the permission a call needs.
+pub const ACCESS_DENIED: ErrorCode = ErrorCode(-32015);
+
+/// The `sync` tool could not exchange counters with its peer.
+pub const SYNC_FAILED: ErrorCode = ErrorCode(-32016);
+
+/// The server is a replication follower, which only serves reads.
+pub const READ_ONLY: ErrorCode = ErrorCode(-32017);
+
+pub fn counter_not_found(name: &str) -> ErrorData {
+    ErrorData::resource_not_found(
+        format!("counter '{name}' does not exist"),
+        Some(json!({ "name": name })),
+    )
+}
+
+pub fn counter_exists(name: &str) -> ErrorData {
+    ErrorData::invalid_params(
+        format!("counter '{name}' already exists"),
+        Some(json!({ "name": name })),
+    )
+}
+
+/// `create_counter` named a counter the caller may not read, which is not
+/// revealed to exist.
+pub fn name_unavailable(name: &str) -> ErrorData {
+    ErrorData::invalid_params(
+        format!("counter name '{name}' is not available"),
+        Some(json!({ "name": name })),
+    )
+}
+
+/// `delete_counter` targeted the counter that calls without a name use.
+pub fn default_undeletable() -> ErrorData {
+    ErrorData::invalid_params(
+        format!("counter '{DEFAULT_COUNTER}' cannot be deleted"),
+        Some(json!({ "name": DEFAULT_COUNTER })),
+    )
+}
+
+/// A step `amount` was zero or negative.
+pub fn non_positive_amount(amount: i64) -> ErrorData {
+    ErrorData::invalid_params(
+        format!("'amount' must be positive, got {amount}"),
+        Some(json!({ "amount": amount })),
+    )
+}
+
+pub fn overflow(name: &str, operation: &str, value: i64, amount: i64) -> ErrorData {
+    ErrorData::new(
+        COUNTER_OVERFLOW,
+        format!("{operation} counter '{name}' ({value}) by {amount} would overflow"),
+        Some(json!({
+            "name": name,
+            "operation": operation,
+            "value": value,
+            "amount": amount,
+        })),
+    )
+}
+
+/// Error for a step that [`crate::Counter::increment`] or
+/// [`crate::Counter::decrement`] refused.
+pub fn step_refused(name: &str, operation: &str, counter: &Counter, amount: i64) -> ErrorData {
+    if !counter.is_bounded() {
+        return overflow(name, operation, counter.value, amount);
+    }
+    ErrorData::new(
+        COUNTER_OUT_OF_BOUNDS,
+        format!(
+            "{operation} counter '{name}' ({}) by {amount} would leave its bounds",
+            counter.value
+        ),
+        Some(json!({
+            "name": name,
+            "operation": operation,
+            "value": counter.value,
+            "amount": amount,
+            "min": counter.min,
+            "max": counter.max,
+        })),
+    )
+}
+
+/// A value given to `set` or `compare_and_set` lies outside the counter's
+/// bounds.
+pub fn out_of_bounds(name: &str, counter: &Counter, value: i64) -> ErrorData {
+    ErrorData::new(
+        COUNTER_OUT_OF_BOUNDS,
+        format!("{value} is outside the bounds of counter '{name}'"),
+        Some(json!({
+            "name": name,
+            "value": value,
+            "min": counter.min,
+            "max": counter.max,
+        })),
+    )
+}
+
+/// The operation does not apply to a rate counter.
+pub fn rate_counter(name: &str, operation: &str) -> ErrorData {
+    ErrorData::invalid_params(
+        format!("counter '{name}' is a rate counter and does not support {operation}"),
+        Some(json!({ "name": name, "operation": operation })),
+    )
+}
+
+/// The operation only applies to a rate counter.
+pub fn not_a_rate_counter(name: &str) -> ErrorData {
+    ErrorData::invalid_params(
+        format!("counter '{name}' is not a rate counter"),
+        Some(json!({ "name": name })),
+    )
+}
+
+pub fn rate_limited(tool: &str, exceeded: &Exceeded) -> ErrorData {
+    let retry_after_ms = u64::try_from(exceeded.retry_after.as_millis()).unwrap_or(u64::MAX);
+    ErrorData::new(
+        RATE_LIMITED,
+        format!("rate limit exceeded for '{tool}'; retry after {retry_after_ms} ms"),
+        Some(json!({
+            "tool": tool,
+            "scope": exceeded.scope,
+            "burst": exceeded.limit.burst,
+            "per_minute": exceeded.limit.per_minute,
+            "retry_after_ms": retry_after_ms,
+        })),
+    )
+}
+
+pub fn quota_exceeded(
+    name: &str,
+    quota: Quota,
+    used: u64,
+    amount: u64,
+    resets_at: DateTime<Utc>,
+) -> ErrorData {
+    let retry_after_ms = u64::try_from((resets_at - Utc::now()).num_milliseconds()).unwrap_or(0);
+    ErrorData::new(
+        QUOTA_EXCEEDED,
+        format!(
+            "raising counter '{name}' by {amount} would exceed its quota of {} per {} s",
+            quota.max, quota.period_secs
+        ),
+        Some(json!({
+            "name": name,
+            "amount": amount,
+            "max": quota.max,
+            "period_secs": quota.period_secs,
+            "used": used,
+            "resets_at": resets_at,
+            "retry_after_ms": retry_after_ms,
+        })),
+    )
+}
+
+pub fn access_denied(name: &str, permission: Permission) -> ErrorData {
+    ErrorData::new(
+        ACCESS_DENIED,
+        format!(
+            "permission '{}' on counter '{name}' is denied",
+            permission.as_str()
+        ),
+        Some(json!({ "name": name, "permission": permission })),
+    )
+}
+
+pub fn counter_limit(max: usize) -> ErrorData {
+    ErrorData::new(
+        COUNTER_LIMIT,
+        format!("the server already holds the maximum of {max} counters"),
+        Some(json!({ "max_counters": max })),
+    )
+}
+
+/// `undo` or `redo` found no operation to step over.
+pub fn nothing_to(action: &str, name: &str) -> ErrorData {
+    ErrorData::invalid_params(
+        format!("counter '{name}' has nothing to {action}"),
+        Some(json!({ "name": name })),
+    )
+}
+
+pub fn idempotency_key_reused(key: &str) -> ErrorData {
+    ErrorData::invalid_params(
+        format!("idempotency key '{key}' was already used for a different call"),
+        Some(json!({ "idempotency_key": key })),
+    )
+}
+
+/// The namespace a session asked for on initialize is not hosted, or not
+/// open to its principal.
+pub fn unknown_namespace(namespace: &str) -> ErrorData {
+    ErrorData::invalid_params(
+        format!("namespace '{namespace}' does not exist"),
+        Some(json!({ "namespace": namespace })),
+    )
+}
+
+pub fn unknown_peer(peer: &str) -> ErrorData {
+    ErrorData::invalid_params(
+        format!("peer '{peer}' is not configured"),
+        Some(json!({ "peer": peer })),
+    )
+}
+
+pub fn sync_failed(peer: &str, error: impl std::fmt::Display) -> ErrorData {
+    ErrorData::new(
+        SYNC_FAILED,
+        format!("failed to sync with peer '{peer}': {error}"),
+        Some(json!({ "peer": peer })),
+    )
+}
+
+/// A write reached a follower. `leader` is the replication address of the
+/// leader it follows, if connected.
+pub fn read_only(leader: Option<std::net::SocketAddr>) -> ErrorData {
+    ErrorData::new(
+        READ_ONLY,
+        "this server is a read-only replication follower; send writes to its leader",
+        Some(json!({ "leader": leader })),
+    )
+}
+
+pub fn audit_disabled() -> ErrorData {
+    ErrorData::invalid_request("the audit log is disabled on this server", None)
+}
+
+pub fn storage_error(error: std::io::Error) -> ErrorData {
+    ErrorData::internal_error(format!("failed to persist counters: {error}"), None)
+}
@@ -0,0 +1,222 @@
+//! Bounded per-counter record of the operations that changed each counter,
+//! backing the `undo`, `redo` and `history` tools.
+//!
+//! History lives in memory only: it starts empty after a restart, and is
+//! dropped when a counter is deleted or created again.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use rmcp::schemars::{self, JsonSchema};
+use serde::Serialize;
+
+use crate::{counter::Counter, wal::Mutation};
+
+/// Number of operations kept per counter unless configured otherwise.
+pub const DEFAULT_HISTORY_LIMIT: usize = 32;
+
+/// Who made a change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct Actor {
+    /// MCP session that made the call.
+    pub session: u64,
+    /// Client name and version reported at initialize, if any.
+    pub client: Option<String>,
+    /// Who the session authenticated as, on an authenticated transport.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub principal: Option<String>,
+}
+
+/// One change to a counter.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Operation {
+    /// Tool that made the change.
+    pub tool: String,
+    pub previous_value: i64,
+    pub value: i64,
+    /// Version of the counter after the change.
+    pub version: u64,
+    pub actor: Actor,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// How a committed set of mutations moves the history.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Change {
+    /// A new operation made by the named tool. Clears anything undone.
+    Apply(&'static str),
+    /// Reverted the latest operation.
+    Undo,
+    /// Reapplied the latest undone operation.
+    Redo,
+}
+
+impl Change {
+    /// Name of the tool making the change.
+    pub(crate) fn tool(self) -> &'static str {
+        match self {
+            Self::Apply(tool) => tool,
+            Self::Undo => "undo",
+            Self::Redo => "redo",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CounterHistory {
+    /// Oldest first.
+    done: VecDeque<Operation>,
+    /// Most recently undone last.
+    undone: Vec<Operation>,
+}
+
+/// Operation history of every counter.
+///
+/// The server only updates it while holding the counters lock, so the
+/// history of a counter always describes its live value.
+#[derive(Clone)]
+pub struct History {
+    limit: usize,
+    counters: Arc<Mutex<HashMap<String, CounterHistory>>>,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            counters: Arc::default(),
+        }
+    }
+
+    /// The operation `undo` would revert.
+    pub fn last_done(&self, name: &str) -> Option<Operation> {
+        let counters = self.counters.lock().expect("history lock poisoned");
+        counters.get(name)?.done.back().cloned()
+    }
+
+    /// The operation `redo` would reapply.
+    pub fn last_undone(&self, name: &str) -> Option<Operation> {
+        let counters = self.counters.lock().expect("history lock poisoned");
+        counters.get(name)?.undone.last().cloned()
+    }
+
+    /// Returns the operations on `name`, oldest first, and those undone, most
+    /// recently undone first.
+    pub fn get(&self, name: &str) -> (Vec<Operation>, Vec<Operation>) {
+        let counters = self.counters.lock().expect("history lock poisoned");
+        match counters.get(name) {
+            Some(history) => (
+                history.done.iter().cloned().collect(),
+                history.undone.iter().rev().cloned().collect(),
+            ),
+            None => Default::default(),
+        }
+    }
+
+    /// Records `mutations`, which were applied to `before`.
+    pub(crate) fn record(
+        &self,
+        change: Change,
+        before: &BTreeMap<String, Counter>,
+        mutations: &[Mutation],
+        actor: &Actor,
+    ) {
+        let mut counters = self.counters.lock().expect("history lock poisoned");
+        for mutation in mutations {
+            let (name, counter, previous) = match mutation {
+                Mutation::Put { name, counter } => match before.get(name) {
+                    Some(previous) => (name, counter, previous),
+                    None => {
+                        counters.remove(name);
+                        continue;
+                    }
+                },
+                Mutation::Delete { name } => {
+                    counters.remove(name);
+                    continue;
+                }
+                Mutation::Remember { .. } => continue,
+            };
+            let history = counters.entry(name.clone()).or_default();
+            match change {
+                Change::Apply(tool) => {
+                    history.undone.clear();
+                    history.done.push_back(Operation {
+                        tool: tool.to_string(),
+                        previous_value: previous.value,
+                        value: counter.value,
+                        version: counter.version,
+                        actor: actor.clone(),
+                        timestamp: Utc::now(),
+                    });
+                    while history.done.len() > self.limit {
+                        history.done.pop_front();
+                    }
+                }
+                Change::Undo => {
+                    if let Some(operation) = history.done.pop_back() {
+                        history.undone.push(operation);
+                    }
+                }
+                Change::Redo => {
+                    if let Some(operation) = history.undone.pop() {
+                        history.done.push_back(operation);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(history: &History, counters: &mut BTreeMap<String, Counter>, change: Change) {
+        let before = counters.clone();
+        counters.get_mut("c").unwrap().increment(1);
+        let mutations = vec![Mutation::Put {
+            name: "c".to_string(),
+            counter: counters["c"].clone(),
+        }];
+        let actor = Actor {
+            session: 1,
+            client: None,
+            principal: None,
+        };
+        history.record(change, &before, &mutations, &actor);
+    }
+
+    #[test]
+    fn keeps_only_the_latest_operations() {
+        let history = History::new(2);
+        let mut counters = BTreeMap::from([("c".to_string(), Counter::default())]);
+        for _ in 0..3 {
+            step(&history, &mut counters, Change::Apply("increment"));
+        }
+        let (done, undone) = history.get("c");
+        let values: Vec<_> = done.iter().map(|op| op.value).collect();
+        assert_eq!(values, [2, 3]);
+        assert!(undone.is_empty());
+    }
+
+    #[test]
+    fn deleting_a_counter_forgets_its_history() {
+        let history = History::new(2);
+        let mut counters = BTreeMap::from([("c".to_string(), Counter::default())]);
+        step(&history, &mut counters, Change::Apply("increment"));
+        let actor = Actor {
+            session: 1,
+            client: None,
+            principal: None,
+        };
+        let delete = [Mutation::Delete {
+            name: "c".to_string(),
+        }];
+        history.record(Change::Apply("delete_counter"), &counters, &delete, &actor);
+        assert!(history.last_done("c").is_none());
+    }
+}
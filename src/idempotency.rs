@@ -0,0 +1,122 @@
+//! Results of recent calls made with an `idempotency_key`, so that a retried
+//! call returns the original result instead of mutating again.
+//!
+//! Entries are written to storage in the same record as the mutations of the
+//! call they belong to, so a call is either applied and remembered or
+//! neither.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use rmcp::model::CallToolResult;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How long a key is remembered unless configured otherwise.
+pub const DEFAULT_IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Tool arguments that may carry an idempotency key.
+pub(crate) trait Idempotent: Serialize {
+    fn idempotency_key(&self) -> Option<&str>;
+}
+
+/// The outcome of a call made with an idempotency key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdempotentResult {
+    pub tool: String,
+    /// Arguments of the original call, used to detect a key reused for a
+    /// different request.
+    pub arguments: Value,
+    pub result: CallToolResult,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Remembered results by key.
+///
+/// Like [`crate::history::History`], the server only reads and updates it
+/// while holding the counters lock, so two calls with the same key cannot
+/// both apply.
+#[derive(Clone)]
+pub struct IdempotencyCache {
+    ttl: Duration,
+    entries: Arc<Mutex<BTreeMap<String, IdempotentResult>>>,
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration, entries: BTreeMap<String, IdempotentResult>) -> Self {
+        let cache = Self {
+            ttl,
+            entries: Arc::new(Mutex::new(entries)),
+        };
+        cache.prune(Utc::now());
+        cache
+    }
+
+    /// When a key remembered now will be forgotten. A TTL reaching past the
+    /// latest representable time means never.
+    pub fn expiry(&self) -> DateTime<Utc> {
+        chrono::Duration::from_std(self.ttl)
+            .ok()
+            .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+
+    /// Returns the unexpired result remembered for `key`.
+    pub fn get(&self, key: &str) -> Option<IdempotentResult> {
+        let entries = self.entries.lock().expect("idempotency lock poisoned");
+        entries
+            .get(key)
+            .filter(|entry| entry.expires_at > Utc::now())
+            .cloned()
+    }
+
+    pub fn insert(&self, key: String, entry: IdempotentResult) {
+        self.prune(Utc::now());
+        self.entries
+            .lock()
+            .expect("idempotency lock poisoned")
+            .insert(key, entry);
+    }
+
+    /// Returns every unexpired entry, for compaction.
+    pub fn entries(&self) -> BTreeMap<String, IdempotentResult> {
+        self.prune(Utc::now());
+        self.entries
+            .lock()
+            .expect("idempotency lock poisoned")
+            .clone()
+    }
+
+    /// Replaces every entry with `entries`.
+    pub fn replace(&self, entries: BTreeMap<String, IdempotentResult>) {
+        *self.entries.lock().expect("idempotency lock poisoned") = entries;
+        self.prune(Utc::now());
+    }
+
+    fn prune(&self, now: DateTime<Utc>) {
+        let mut entries = self.entries.lock().expect("idempotency lock poisoned");
+        entries.retain(|_, entry| entry.expires_at > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huge_ttls_expire_at_the_end_of_time() {
+        let cache = IdempotencyCache::new(Duration::from_secs(u64::MAX), BTreeMap::new());
+        let expiry = cache.expiry();
+        assert_eq!(expiry, DateTime::<Utc>::MAX_UTC);
+        // Storage must be able to read it back.
+        let json = serde_json::to_string(&expiry).unwrap();
+        assert_eq!(
+            serde_json::from_str::<DateTime<Utc>>(&json).unwrap(),
+            expiry
+        );
+    }
+}
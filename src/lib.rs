@@ -0,0 +1,34 @@
+//! An MCP server exposing named counters as tools and resources.
+//!
+//! [`CounterServer`] implements [`rmcp::ServerHandler`] and can be served
+//! over any rmcp transport. To mount the counter tools inside another
+//! server, delegate to [`CounterServer::tool_router`].
+
+pub mod acl;
+pub mod audit;
+pub mod auth;
+mod builder;
+pub mod config;
+pub mod counter;
+pub mod crdt;
+pub mod error;
+pub mod history;
+pub mod idempotency;
+pub mod logging;
+pub mod metrics;
+pub mod namespace;
+pub mod output;
+mod peer;
+pub mod rate;
+pub mod rate_limit;
+pub mod replication;
+pub mod resources;
+mod server;
+pub mod storage;
+pub mod wal;
+
+pub use builder::{CounterServerBuilder, DEFAULT_COMPACT_THRESHOLD};
+pub use config::Config;
+pub use counter::{Counter, CounterKind, OverflowPolicy};
+pub use server::{CounterServer, DEFAULT_COUNTER, DEFAULT_INSTRUCTIONS};
+pub use storage::{JsonFileStorage, Storage};
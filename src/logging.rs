@@ -0,0 +1,296 @@
+//! Forwards the server's `tracing` events to MCP clients as
+//! `notifications/message`.
+//!
+//! A client opts in with `logging/setLevel`. From then on it receives the
+//! events of this crate at or above that level that were emitted while
+//! serving one of its own requests, plus events of background work for its
+//! namespace, such as a failed compaction. Events from other sessions and
+//! namespaces, and those outside any session or namespace span, such as
+//! replication and startup messages, are never sent.
+//!
+//! The embedder installs [`ClientLogs::layer`] in its subscriber and passes
+//! the same [`ClientLogs`] to [`crate::CounterServerBuilder::client_logs`].
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use rmcp::{
+    Peer, RoleServer,
+    model::{LoggingLevel, LoggingMessageNotificationParam},
+};
+use serde_json::{Map, Value};
+use tokio::sync::mpsc;
+use tracing::{
+    Event, Level, Subscriber,
+    field::{Field, Visit},
+    span,
+};
+use tracing_subscriber::{Layer, filter::Targets, layer::Context, registry::LookupSpan};
+
+/// Name of the span field carrying the session a span belongs to.
+pub(crate) const SESSION_FIELD: &str = "session";
+
+/// Name of the span field carrying the namespace a span works in.
+pub(crate) const NAMESPACE_FIELD: &str = "namespace";
+
+struct ClientLogger {
+    namespace: String,
+    level: LoggingLevel,
+    messages: mpsc::UnboundedSender<LoggingMessageNotificationParam>,
+}
+
+/// The sessions that asked for log messages, and at what level.
+#[derive(Clone, Default)]
+pub struct ClientLogs {
+    sessions: Arc<Mutex<HashMap<u64, ClientLogger>>>,
+}
+
+impl ClientLogs {
+    /// Sends `session`, which works in `namespace`, every event at or above
+    /// `level` from now on. Must be called from within a Tokio runtime.
+    pub fn set_level(
+        &self,
+        session: u64,
+        namespace: &str,
+        peer: Peer<RoleServer>,
+        level: LoggingLevel,
+    ) {
+        let mut sessions = self.sessions.lock().expect("client logs lock poisoned");
+        if let Some(logger) = sessions.get_mut(&session) {
+            logger.level = level;
+            return;
+        }
+        // Messages go through a channel so that they keep their order and
+        // events can be forwarded without awaiting the transport.
+        let (messages, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                if peer.notify_logging_message(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let namespace = namespace.to_string();
+        sessions.insert(
+            session,
+            ClientLogger {
+                namespace,
+                level,
+                messages,
+            },
+        );
+    }
+
+    /// A layer forwarding this crate's events to the sessions that asked for
+    /// them.
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        ClientLogLayer { logs: self.clone() }
+            .with_filter(Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::TRACE))
+    }
+
+    fn send(&self, origin: &Origin, level: LoggingLevel, message: impl Fn() -> Value) {
+        let mut sessions = self.sessions.lock().expect("client logs lock poisoned");
+        // A closed receiver means the session's transport went away.
+        sessions.retain(|_, logger| !logger.messages.is_closed());
+        for (id, logger) in sessions.iter() {
+            let recipient = match origin {
+                Origin::Session(session) => session == id,
+                Origin::Namespace(namespace) => *namespace == logger.namespace,
+            };
+            if recipient && severity(level) >= severity(logger.level) {
+                let _ = logger.messages.send(LoggingMessageNotificationParam {
+                    level,
+                    logger: Some(env!("CARGO_CRATE_NAME").to_string()),
+                    data: message(),
+                });
+            }
+        }
+    }
+}
+
+/// Session a span was opened for, stored in its extensions.
+struct SpanSession(u64);
+
+/// Namespace a span works in, stored in its extensions.
+struct SpanNamespace(String);
+
+/// Who an event concerns, and so who may receive it.
+enum Origin {
+    Session(u64),
+    Namespace(String),
+}
+
+struct ClientLogLayer {
+    logs: ClientLogs,
+}
+
+impl<S> Layer<S> for ClientLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut fields = JsonFields::default();
+        attrs.record(&mut fields);
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if let Some(session) = fields.0.get(SESSION_FIELD).and_then(Value::as_u64) {
+            span.extensions_mut().insert(SpanSession(session));
+        }
+        if let Some(namespace) = fields.0.get(NAMESPACE_FIELD).and_then(Value::as_str) {
+            span.extensions_mut()
+                .insert(SpanNamespace(namespace.to_string()));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut origin = None;
+        for span in ctx.event_scope(event).into_iter().flatten() {
+            let extensions = span.extensions();
+            if let Some(session) = extensions.get::<SpanSession>() {
+                origin = Some(Origin::Session(session.0));
+                break;
+            }
+            if let (None, Some(namespace)) = (&origin, extensions.get::<SpanNamespace>()) {
+                origin = Some(Origin::Namespace(namespace.0.clone()));
+            }
+        }
+        // Events that concern no session or namespace may carry addresses
+        // and other details no client should see.
+        let Some(origin) = origin else {
+            return;
+        };
+        let level = logging_level(*event.metadata().level());
+        self.logs.send(&origin, level, || {
+            let mut fields = JsonFields::default();
+            event.record(&mut fields);
+            Value::Object(fields.0)
+        });
+    }
+}
+
+fn logging_level(level: Level) -> LoggingLevel {
+    match level {
+        Level::ERROR => LoggingLevel::Error,
+        Level::WARN => LoggingLevel::Warning,
+        Level::INFO => LoggingLevel::Info,
+        _ => LoggingLevel::Debug,
+    }
+}
+
+fn severity(level: LoggingLevel) -> u8 {
+    match level {
+        LoggingLevel::Debug => 0,
+        LoggingLevel::Info => 1,
+        LoggingLevel::Notice => 2,
+        LoggingLevel::Warning => 3,
+        LoggingLevel::Error => 4,
+        LoggingLevel::Critical => 5,
+        LoggingLevel::Alert => 6,
+        LoggingLevel::Emergency => 7,
+    }
+}
+
+/// Collects the fields of an event or span into a JSON object.
+#[derive(Default)]
+struct JsonFields(Map<String, Value>);
+
+impl Visit for JsonFields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}").into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[test]
+    fn events_become_json_objects() {
+        let logs = ClientLogs::default();
+        let (messages, mut rx) = mpsc::unbounded_channel();
+        logs.sessions.lock().unwrap().insert(
+            7,
+            ClientLogger {
+                namespace: "default".to_string(),
+                level: LoggingLevel::Info,
+                messages,
+            },
+        );
+        let subscriber = tracing_subscriber::registry().with(logs.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("too detailed");
+            tracing::info_span!("call_tool", session = 8_u64).in_scope(|| {
+                tracing::warn!("for another session");
+            });
+            tracing::info_span!("call_tool", session = 7_u64).in_scope(|| {
+                tracing::warn!(tool = "increment", amount = 3, "slow call");
+            });
+        });
+
+        let message = rx.try_recv().unwrap();
+        assert_eq!(message.level, LoggingLevel::Warning);
+        assert_eq!(
+            message.data,
+            serde_json::json!({ "message": "slow call", "tool": "increment", "amount": 3 })
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn background_events_reach_only_their_namespace() {
+        let logs = ClientLogs::default();
+        let (messages, mut rx) = mpsc::unbounded_channel();
+        logs.sessions.lock().unwrap().insert(
+            7,
+            ClientLogger {
+                namespace: "team-a".to_string(),
+                level: LoggingLevel::Info,
+                messages,
+            },
+        );
+        let subscriber = tracing_subscriber::registry().with(logs.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("compaction", namespace = "team-b").in_scope(|| {
+                tracing::warn!("failed to compact storage");
+            });
+            tracing::info_span!("compaction", namespace = "team-a").in_scope(|| {
+                tracing::warn!("failed to compact storage");
+            });
+            tracing::warn!("taking over as leader");
+        });
+
+        let own = rx.try_recv().unwrap();
+        assert_eq!(own.data["message"], "failed to compact storage");
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -1,96 +1,281 @@
-use std::{borrow::Cow, sync::Arc};
-use tokio::sync::Mutex;
+use std::{fs::OpenOptions, io::IsTerminal, net::SocketAddr, path::PathBuf, sync::Mutex};
 
-use rmcp::{RoleServer,service::RequestContext,
-    handler::server::tool::ToolCallContext, model::{
-        CallToolRequestParam, CallToolResult, Content, ErrorCode, Implementation, ListToolsResult, PaginatedRequestParam, ProtocolVersion, ServerCapabilities, ServerInfo
-    }, tool, tool_router, transport::stdio, ErrorData, ServerHandler, ServiceExt,
+use clap::{Parser, Subcommand};
+use rmcp::{
+    ServiceExt,
+    transport::{
+        sse_server::{SseServer, SseServerConfig},
+        stdio,
+        streamable_http_server::{StreamableHttpService, session::local::LocalSessionManager},
+    },
 };
+use rust_counter_mcp::{
+    Config, CounterServer, JsonFileStorage,
+    audit::AuditLog,
+    auth::{Authenticator, hash_token, require_bearer},
+    config::{CONFIG_ENV, TransportKind},
+    crdt,
+    logging::ClientLogs,
+    metrics, namespace,
+    replication::{self, Role},
+    storage::DATA_DIR_ENV,
+};
+use tracing_subscriber::{EnvFilter, Layer, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Address the HTTP transport binds to when neither `--bind` nor the config
+/// file gives one.
+const DEFAULT_BIND: &str = "127.0.0.1:8000";
 
-#[derive(Clone)]
-pub struct HelloWorld {
-    counter: Arc<Mutex<i32>>,
+/// Log filter used when neither `--log-level` nor the config file gives one.
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// MCP server exposing named counters.
+///
+/// Flags take precedence over their environment variables, which take
+/// precedence over the config file.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML file to read settings from.
+    #[arg(long, env = CONFIG_ENV)]
+    config: Option<PathBuf>,
+    /// Transport to serve: stdio or http.
+    #[arg(long, env = "COUNTER_MCP_TRANSPORT")]
+    transport: Option<TransportKind>,
+    /// Address the HTTP transport listens on [default: 127.0.0.1:8000].
+    #[arg(long, env = "COUNTER_MCP_BIND")]
+    bind: Option<SocketAddr>,
+    /// Directory holding the counter snapshot and log.
+    #[arg(long, env = DATA_DIR_ENV)]
+    data_dir: Option<PathBuf>,
+    /// Log filter, e.g. `info` or `rust_counter_mcp=debug` [default: info].
+    #[arg(long, env = "COUNTER_MCP_LOG_LEVEL")]
+    log_level: Option<String>,
+    /// File to append logs to instead of stderr.
+    #[arg(long, env = "COUNTER_MCP_LOG_FILE")]
+    log_file: Option<PathBuf>,
+    /// Largest number of counters clients may create.
+    #[arg(long, env = "COUNTER_MCP_MAX_COUNTERS")]
+    max_counters: Option<usize>,
+    /// TOML file of token hashes that HTTP clients must present as bearer
+    /// tokens.
+    #[arg(long, env = "COUNTER_MCP_TOKEN_FILE")]
+    token_file: Option<PathBuf>,
+    /// Address to serve Prometheus metrics on at `/metrics`.
+    #[arg(long, env = "COUNTER_MCP_METRICS_BIND")]
+    metrics_bind: Option<SocketAddr>,
+    /// Identifies this instance to the peers it syncs counters with
+    /// [default: generated and kept in the data directory].
+    #[arg(long, env = "COUNTER_MCP_REPLICA_ID")]
+    replica_id: Option<String>,
+    /// Address to stream the mutation log to followers on while leading.
+    #[arg(long, env = "COUNTER_MCP_REPLICATION_BIND")]
+    replication_bind: Option<SocketAddr>,
+    /// Follow the leader at this replication address, serving reads only.
+    /// Repeat or separate with commas to list several, tried in turn.
+    #[arg(long, env = "COUNTER_MCP_FOLLOW", value_delimiter = ',')]
+    follow: Vec<SocketAddr>,
+    /// Seconds a follower goes without a leader before taking over.
+    #[arg(long, env = "COUNTER_MCP_FAILOVER_SECS")]
+    failover_secs: Option<u64>,
 }
 
-#[tool_router]
-impl HelloWorld {
-    pub fn new() -> Self {
-        Self {
-            counter: Arc::new(Mutex::new(0)),
+#[derive(Subcommand)]
+enum Command {
+    /// Reads a token from stdin and prints the token file entry for it.
+    HashToken {
+        /// Name the token is issued to.
+        principal: String,
+    },
+}
+
+impl Cli {
+    /// Reads the config file, if any, and lays the flags over it.
+    fn into_config(self) -> std::io::Result<Config> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        config.transport = self.transport.or(config.transport);
+        config.bind = self.bind.or(config.bind);
+        config.data_dir = self.data_dir.or(config.data_dir);
+        config.log_level = self.log_level.or(config.log_level);
+        config.log_file = self.log_file.or(config.log_file);
+        config.limits.max_counters = self.max_counters.or(config.limits.max_counters);
+        config.auth.token_file = self.token_file.or(config.auth.token_file);
+        config.metrics.bind = self.metrics_bind.or(config.metrics.bind);
+        config.replica_id = self.replica_id.or(config.replica_id);
+        let replication = &mut config.replication;
+        replication.bind = self.replication_bind.or(replication.bind);
+        if !self.follow.is_empty() {
+            replication.role = Role::Follower;
+            replication.leaders = self.follow;
         }
+        replication.failover_secs = self.failover_secs.or(replication.failover_secs);
+        Ok(config)
     }
+}
 
-    #[tool(name = "increment", description = "Tool that increments and decrements a counter")]
-    async fn increment(&self) -> Result<CallToolResult, ErrorData> {
-        let mut count = self.counter.lock().await;
-        *count += 1;
-        Ok(CallToolResult::success(vec![Content::text(
-            count.to_string(),
-        )]))
+/// Serves streamable HTTP at `/mcp` and legacy SSE at `/sse` + `/message`.
+///
+/// Every session gets its own handle from [`CounterServer::new_session`], so all
+/// clients in a namespace share the same counters. With `auth`, every request
+/// needs a bearer token.
+async fn serve_http(
+    server: CounterServer,
+    bind: SocketAddr,
+    auth: Option<Authenticator>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ct = tokio_util::sync::CancellationToken::new();
+    let (sse_server, sse_router) = SseServer::new(SseServerConfig {
+        bind,
+        sse_path: "/sse".to_string(),
+        post_path: "/message".to_string(),
+        ct: ct.clone(),
+        sse_keep_alive: None,
+    });
+    let streamable = {
+        let server = server.clone();
+        StreamableHttpService::new(
+            move || Ok(server.new_session()),
+            LocalSessionManager::default().into(),
+            Default::default(),
+        )
+    };
+    let mut router = sse_router.nest_service("/mcp", streamable);
+    if let Some(auth) = auth {
+        router = router.layer(axum::middleware::from_fn_with_state(auth, require_bearer));
     }
+    sse_server.with_service(move || server.new_session());
 
-    #[tool(name = "decrement", description = "Tool that decrements a counter")]
-    async fn decrement(&self) -> Result<CallToolResult, ErrorData> {
-        let mut count = self.counter.lock().await;
-        *count -= 1;
-        Ok(CallToolResult::success(vec![Content::text(
-            count.to_string(),
-        )]))
-    }
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            ct.cancel();
+        })
+        .await?;
+    Ok(())
+}
 
-    #[tool(name = "get_counter", description = "Tool that returns the current value of the counter")]
-    async fn get_counter(&self) -> Result<CallToolResult, ErrorData> {
-        let count = self.counter.lock().await;
-        Ok(CallToolResult::success(vec![Content::text(
-            count.to_string(),
-        )]))
-    }
+/// Serves `GET /metrics` on its own listener, so that it can stay private
+/// when the MCP transport is exposed.
+async fn serve_metrics(server: CounterServer, bind: SocketAddr) -> std::io::Result<()> {
+    let router = axum::Router::new().route(
+        "/metrics",
+        axum::routing::get(move || async move {
+            (
+                [(axum::http::header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+                server.render_metrics().await,
+            )
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    axum::serve(listener, router).await
 }
 
-impl ServerHandler for HelloWorld {
-    fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
-            server_info: Implementation::from_build_env(),
-            instructions: Some(
-                "This server provide counter tools that can increment, decrement, and retrieve the current value of a counter. Use the 'increment', 'decrement', and 'get_counter' tools to interact with the counter."
-                    .to_string(),
-            ),
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    if let Some(Command::HashToken { principal }) = &cli.command {
+        let mut token = String::new();
+        std::io::stdin().read_line(&mut token)?;
+        println!(
+            "[tokens.{principal:?}]\nsha256 = \"{}\"",
+            hash_token(token.trim())
+        );
+        return Ok(());
+    }
+    let mut config = cli.into_config()?;
+
+    // Logs go to stderr or a file so they never interleave with the stdio
+    // transport. Clients that ask for logs get them regardless of the filter.
+    let filter = EnvFilter::try_new(config.log_level.as_deref().unwrap_or(DEFAULT_LOG_LEVEL))?;
+    let output = match &config.log_file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            fmt::layer()
+                .with_writer(Mutex::new(file))
+                .with_ansi(false)
+                .boxed()
         }
+        None => fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_ansi(std::io::stderr().is_terminal())
+            .boxed(),
+    };
+    let client_logs = ClientLogs::default();
+    tracing_subscriber::registry()
+        .with(output.with_filter(filter))
+        .with(client_logs.layer())
+        .init();
+
+    let data_dir = config
+        .data_dir
+        .clone()
+        .unwrap_or_else(JsonFileStorage::default_dir);
+    tracing::info!(data_dir = %data_dir.display(), "loading counters");
+    config
+        .replication
+        .term_file
+        .get_or_insert_with(|| data_dir.join(replication::TERM_FILE));
+    let mut builder = CounterServer::builder()
+        .config(&config)
+        .client_logs(client_logs);
+    if config.audit.enabled {
+        let audit = AuditLog::open(&data_dir)?
+            .max_file_bytes(config.audit.max_file_bytes)
+            .max_files(config.audit.max_files);
+        builder = builder.audit(audit);
     }
+    let replica_id = match &config.replica_id {
+        Some(id) => id.clone(),
+        None => crdt::load_or_create_replica_id(&data_dir)?,
+    };
+    tracing::info!(replica_id, "identifying as replica");
+    builder = builder.replica_id(replica_id);
+    for name in config.namespaces.keys() {
+        let dir = namespace::data_dir(&data_dir, name)?;
+        builder = builder.namespace_storage(name.clone(), JsonFileStorage::open(dir)?);
+    }
+    let server = builder.storage(JsonFileStorage::open(data_dir)?).build()?;
 
-    async fn list_tools(
-        &self,
-        _pagination: Option<PaginatedRequestParam>,
-        _ctx: RequestContext<RoleServer>,
-    ) -> Result<ListToolsResult, ErrorData> {
-        let tools = Self::tool_router().list_all();
-        Ok(ListToolsResult {
-            tools,
-            next_cursor: None,
-        })
+    if let Some(bind) = config.metrics.bind {
+        let server = server.clone();
+        tracing::info!(%bind, "serving metrics at /metrics");
+        tokio::spawn(async move {
+            if let Err(error) = serve_metrics(server, bind).await {
+                tracing::error!(%error, "metrics listener failed");
+            }
+        });
     }
-    async fn call_tool(
-            &self,
-            params: CallToolRequestParam,
-            ctx: RequestContext<RoleServer>,
-        ) -> Result<CallToolResult, ErrorData> {
-            let context = ToolCallContext {
-                request_context: ctx,
-                service: self,
-                name: params.name,
-                arguments: params.arguments,
+
+    match config.transport.unwrap_or_default() {
+        TransportKind::Stdio => {
+            tracing::info!("serving on stdio");
+            let service = server.serve(stdio()).await?;
+            service.waiting().await?;
+        }
+        TransportKind::Http => {
+            let bind = match config.bind {
+                Some(bind) => bind,
+                None => DEFAULT_BIND.parse()?,
+            };
+            let auth = match &config.auth.token_file {
+                Some(path) => Some(Authenticator::load(path)?),
+                None => {
+                    tracing::warn!(
+                        "no token file configured; the HTTP transport is unauthenticated"
+                    );
+                    None
+                }
             };
-            Self::tool_router().call(context).await
+            tracing::info!(%bind, "serving streamable HTTP at /mcp and SSE at /sse");
+            serve_http(server, bind, auth).await?
         }
-}
-
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let service = HelloWorld::new().serve(stdio()).await?;
-    service.waiting().await?;
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,279 @@
+//! Server metrics in the Prometheus text exposition format.
+//!
+//! [`Metrics`] collects per-tool call counts, errors and latencies from
+//! `call_tool` and tracks active sessions; [`Metrics::render`] combines them
+//! with the current counter values.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicI64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::counter::Counter;
+
+/// Content type of [`Metrics::render`]'s output.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Upper bounds, in seconds, of the tool call latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0,
+];
+
+#[derive(Debug, Default)]
+struct ToolStats {
+    calls: u64,
+    errors: u64,
+    /// Observations per bucket of [`LATENCY_BUCKETS`], not cumulative.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    seconds: f64,
+}
+
+/// Metrics shared by every session of a server.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    tools: Arc<Mutex<BTreeMap<String, ToolStats>>>,
+    sessions: Arc<AtomicI64>,
+}
+
+/// Counts one active session until dropped.
+#[derive(Debug)]
+pub struct SessionGuard {
+    sessions: Arc<AtomicI64>,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Records a call to `tool` that took `elapsed` and failed if `failed`.
+    pub fn record_call(&self, tool: &str, elapsed: Duration, failed: bool) {
+        let mut tools = self.tools.lock().expect("metrics lock poisoned");
+        let stats = tools.entry(tool.to_string()).or_default();
+        stats.calls += 1;
+        if failed {
+            stats.errors += 1;
+        }
+        let seconds = elapsed.as_secs_f64();
+        stats.seconds += seconds;
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            stats.buckets[bucket] += 1;
+        }
+    }
+
+    /// Counts a session as active for as long as the guard lives.
+    pub fn session_started(&self) -> SessionGuard {
+        self.sessions.fetch_add(1, Ordering::Relaxed);
+        SessionGuard {
+            sessions: self.sessions.clone(),
+        }
+    }
+
+    /// Renders every metric, with one gauge sample per counter of each
+    /// `(namespace, counters)` pair.
+    pub fn render(&self, namespaces: &[(&str, &BTreeMap<String, Counter>)]) -> String {
+        let mut out = String::new();
+
+        family(
+            &mut out,
+            "counter_mcp_counter_value",
+            "gauge",
+            "Current value of each counter.",
+        );
+        for (namespace, name, counter) in counters(namespaces) {
+            sample(
+                &mut out,
+                "counter_mcp_counter_value",
+                &[("namespace", namespace), ("name", name)],
+                counter.value,
+            );
+        }
+        family(
+            &mut out,
+            "counter_mcp_counter_version",
+            "gauge",
+            "Number of writes each counter has accepted.",
+        );
+        for (namespace, name, counter) in counters(namespaces) {
+            sample(
+                &mut out,
+                "counter_mcp_counter_version",
+                &[("namespace", namespace), ("name", name)],
+                counter.version,
+            );
+        }
+        family(
+            &mut out,
+            "counter_mcp_active_sessions",
+            "gauge",
+            "MCP sessions currently initialized.",
+        );
+        sample(
+            &mut out,
+            "counter_mcp_active_sessions",
+            &[],
+            self.sessions.load(Ordering::Relaxed),
+        );
+
+        let tools = self.tools.lock().expect("metrics lock poisoned");
+        family(
+            &mut out,
+            "counter_mcp_tool_calls_total",
+            "counter",
+            "Tool calls by tool name.",
+        );
+        for (tool, stats) in tools.iter() {
+            sample(
+                &mut out,
+                "counter_mcp_tool_calls_total",
+                &[("tool", tool)],
+                stats.calls,
+            );
+        }
+        family(
+            &mut out,
+            "counter_mcp_tool_errors_total",
+            "counter",
+            "Tool calls that failed, by tool name.",
+        );
+        for (tool, stats) in tools.iter() {
+            sample(
+                &mut out,
+                "counter_mcp_tool_errors_total",
+                &[("tool", tool)],
+                stats.errors,
+            );
+        }
+        family(
+            &mut out,
+            "counter_mcp_tool_duration_seconds",
+            "histogram",
+            "Tool call latency by tool name.",
+        );
+        for (tool, stats) in tools.iter() {
+            let mut cumulative = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(stats.buckets) {
+                cumulative += count;
+                let le = le.to_string();
+                let labels = [("tool", tool.as_str()), ("le", &le)];
+                sample(
+                    &mut out,
+                    "counter_mcp_tool_duration_seconds_bucket",
+                    &labels,
+                    cumulative,
+                );
+            }
+            let labels = [("tool", tool.as_str()), ("le", "+Inf")];
+            sample(
+                &mut out,
+                "counter_mcp_tool_duration_seconds_bucket",
+                &labels,
+                stats.calls,
+            );
+            sample(
+                &mut out,
+                "counter_mcp_tool_duration_seconds_sum",
+                &[("tool", tool)],
+                stats.seconds,
+            );
+            sample(
+                &mut out,
+                "counter_mcp_tool_duration_seconds_count",
+                &[("tool", tool)],
+                stats.calls,
+            );
+        }
+        out
+    }
+}
+
+/// Every counter of `namespaces` with the namespace it belongs to.
+fn counters<'a>(
+    namespaces: &'a [(&'a str, &'a BTreeMap<String, Counter>)],
+) -> impl Iterator<Item = (&'a str, &'a str, &'a Counter)> {
+    namespaces.iter().flat_map(|(namespace, counters)| {
+        counters
+            .iter()
+            .map(move |(name, counter)| (*namespace, name.as_str(), counter))
+    })
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        out.push('{');
+        for (i, (key, value)) in labels.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{key}=\"{}\"", escape(value));
+        }
+        out.push('}');
+    }
+    let _ = writeln!(out, " {value}");
+}
+
+/// Escapes a label value as the exposition format requires.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters_tools_and_sessions() {
+        let metrics = Metrics::default();
+        metrics.record_call("increment", Duration::from_millis(2), false);
+        metrics.record_call("increment", Duration::from_secs(2), true);
+        let _session = metrics.session_started();
+        let counters = BTreeMap::from([(
+            "say \"hi\"".to_string(),
+            Counter {
+                value: -3,
+                ..Counter::default()
+            },
+        )]);
+
+        let text = metrics.render(&[("default", &counters)]);
+        assert!(text.contains(
+            "counter_mcp_counter_value{namespace=\"default\",name=\"say \\\"hi\\\"\"} -3\n"
+        ));
+        assert!(text.contains("counter_mcp_active_sessions 1\n"));
+        assert!(text.contains("counter_mcp_tool_calls_total{tool=\"increment\"} 2\n"));
+        assert!(text.contains("counter_mcp_tool_errors_total{tool=\"increment\"} 1\n"));
+        assert!(text.contains(
+            "counter_mcp_tool_duration_seconds_bucket{tool=\"increment\",le=\"0.0025\"} 1\n"
+        ));
+        assert!(text.contains(
+            "counter_mcp_tool_duration_seconds_bucket{tool=\"increment\",le=\"+Inf\"} 2\n"
+        ));
+    }
+
+    #[test]
+    fn dropping_the_guard_ends_the_session() {
+        let metrics = Metrics::default();
+        drop(metrics.session_started());
+        assert!(
+            metrics
+                .render(&[])
+                .contains("counter_mcp_active_sessions 0\n")
+        );
+    }
+}
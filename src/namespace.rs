@@ -0,0 +1,254 @@
+//! Namespaces: isolated sets of counters hosted by one server.
+//!
+//! Each namespace has its own counters, storage, limits, history,
+//! idempotency keys and resource subscriptions. A session is bound to one
+//! namespace when it initializes and never sees the counters of another.
+//! Counters created before namespaces existed live in [`DEFAULT_NAMESPACE`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::AtomicU64},
+    time::Duration,
+};
+
+use tokio::sync::{Mutex, Notify};
+use tracing::Instrument;
+
+use crate::{
+    config::Limits,
+    counter::Counter,
+    crdt::INITIAL_REPLICA,
+    history::{DEFAULT_HISTORY_LIMIT, History},
+    idempotency::{IdempotencyCache, IdempotentResult},
+    logging,
+    resources::{Subscriptions, counter_uri},
+    server::DEFAULT_COUNTER,
+    storage::{Snapshot, Storage},
+    wal::Mutation,
+};
+
+/// Namespace of sessions that do not ask for one.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// HTTP header a client sends with `initialize` to pick its namespace.
+pub const NAMESPACE_HEADER: &str = "counter-namespace";
+
+/// Directory under the data directory holding one subdirectory per
+/// namespace other than the default, which keeps the data directory itself.
+const NAMESPACES_DIR: &str = "namespaces";
+
+/// Checks that `name` is 1 to 64 ASCII letters, digits, `-` or `_`, so that
+/// it is safe to use as a directory name.
+pub fn validate_name(name: &str) -> io::Result<()> {
+    let valid = (1..=64).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid namespace name '{name}'"),
+        ))
+    }
+}
+
+/// Directory under `data_dir` that persists the namespace `name`.
+pub fn data_dir(data_dir: &Path, name: &str) -> io::Result<PathBuf> {
+    validate_name(name)?;
+    if name == DEFAULT_NAMESPACE {
+        return Ok(data_dir.to_path_buf());
+    }
+    Ok(data_dir.join(NAMESPACES_DIR).join(name))
+}
+
+/// The state of one namespace.
+pub(crate) struct Namespace {
+    pub(crate) name: String,
+    pub(crate) counters: Mutex<BTreeMap<String, Counter>>,
+    storage: Option<Arc<dyn Storage>>,
+    compaction: Notify,
+    compact_threshold: usize,
+    pub(crate) limits: Limits,
+    /// Replaces the server's instructions for sessions in this namespace.
+    pub(crate) instructions: Option<String>,
+    /// Principals allowed to bind sessions to the namespace; anyone when
+    /// absent.
+    pub(crate) principals: Option<BTreeSet<String>>,
+    pub(crate) subscriptions: Subscriptions,
+    pub(crate) history: History,
+    pub(crate) idempotency: IdempotencyCache,
+    /// Number of writes streamed to followers; see [`crate::replication`].
+    pub(crate) replication_seq: AtomicU64,
+}
+
+/// What the builder gathers for a namespace.
+#[derive(Default)]
+pub(crate) struct NamespaceParts {
+    pub(crate) storage: Option<Arc<dyn Storage>>,
+    pub(crate) counters: BTreeMap<String, Counter>,
+    pub(crate) limits: Limits,
+    pub(crate) instructions: Option<String>,
+    pub(crate) principals: Option<BTreeSet<String>>,
+}
+
+impl Namespace {
+    /// Loads the persisted counters of namespace `name` and adds the initial
+    /// counters storage does not hold yet. Values not yet reflected in a
+    /// counter's replica state are credited to [`INITIAL_REPLICA`].
+    pub(crate) fn load(
+        name: String,
+        mut parts: NamespaceParts,
+        compact_threshold: usize,
+        idempotency_ttl: Duration,
+    ) -> io::Result<Self> {
+        let mut snapshot = match &parts.storage {
+            Some(storage) => storage.load()?,
+            None => Snapshot::default(),
+        };
+        for (counter_name, counter) in std::mem::take(&mut parts.counters) {
+            counter.validate().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("counter '{counter_name}' in namespace '{name}': {e}"),
+                )
+            })?;
+            snapshot.counters.entry(counter_name).or_insert(counter);
+        }
+        let mut counters = snapshot.counters;
+        counters.entry(DEFAULT_COUNTER.to_string()).or_default();
+        for counter in counters.values_mut() {
+            counter.reconcile(INITIAL_REPLICA);
+        }
+        Ok(Self {
+            name,
+            counters: Mutex::new(counters),
+            storage: parts.storage,
+            compaction: Notify::new(),
+            compact_threshold,
+            limits: parts.limits,
+            instructions: parts.instructions,
+            principals: parts.principals,
+            subscriptions: Subscriptions::default(),
+            history: History::new(parts.limits.history.unwrap_or(DEFAULT_HISTORY_LIMIT)),
+            idempotency: IdempotencyCache::new(idempotency_ttl, snapshot.idempotency),
+            replication_seq: AtomicU64::new(0),
+        })
+    }
+
+    /// Durably logs `mutations`, and wakes the compaction task once the log
+    /// has grown past the threshold. Does nothing without storage.
+    pub(crate) fn persist(&self, mutations: Vec<Mutation>) -> io::Result<()> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+        storage.append(mutations)?;
+        if storage.pending() >= self.compact_threshold {
+            self.compaction.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Replaces the counters and remembered results with a leader's, and
+    /// persists them as the new snapshot.
+    pub(crate) async fn replace(
+        &self,
+        counters: BTreeMap<String, Counter>,
+        idempotency: BTreeMap<String, IdempotentResult>,
+    ) -> io::Result<()> {
+        let mut current = self.counters.lock().await;
+        if let Some(storage) = &self.storage {
+            storage.compact(&counters, &idempotency)?;
+        }
+        let names: BTreeSet<_> = current.keys().chain(counters.keys()).collect();
+        let updated: Vec<_> = names
+            .into_iter()
+            .filter(|name| current.get(*name) != counters.get(*name))
+            .map(|name| counter_uri(name))
+            .collect();
+        self.idempotency.replace(idempotency);
+        *current = counters;
+        drop(current);
+        self.subscriptions.notify_updated(&updated).await;
+        Ok(())
+    }
+
+    /// Persists and applies mutations committed by a leader.
+    pub(crate) async fn apply(&self, mutations: Vec<Mutation>) -> io::Result<()> {
+        let mut counters = self.counters.lock().await;
+        self.persist(mutations.clone())?;
+        let mut updated = Vec::new();
+        for mutation in mutations {
+            match mutation {
+                Mutation::Put { name, counter } => {
+                    updated.push(counter_uri(&name));
+                    counters.insert(name, counter);
+                }
+                Mutation::Delete { name } => {
+                    updated.push(counter_uri(&name));
+                    counters.remove(&name);
+                }
+                Mutation::Remember { key, entry } => self.idempotency.insert(key, entry),
+            }
+        }
+        drop(counters);
+        self.subscriptions.notify_updated(&updated).await;
+        Ok(())
+    }
+
+    /// Spawns the task that compacts the storage log once it grows past the
+    /// configured threshold. Must be called from within a Tokio runtime.
+    pub(crate) fn start_compaction(self: &Arc<Self>) {
+        let Some(storage) = &self.storage else {
+            return;
+        };
+        if storage.pending() > 0 {
+            self.compaction.notify_one();
+        }
+        let span = tracing::info_span!(
+            "compaction",
+            { logging::NAMESPACE_FIELD } = self.name.as_str()
+        );
+        tokio::spawn(self.clone().compact_in_background().instrument(span));
+    }
+
+    async fn compact_in_background(self: Arc<Self>) {
+        let Some(storage) = self.storage.clone() else {
+            return;
+        };
+        loop {
+            self.compaction.notified().await;
+            let counters = self.counters.lock().await;
+            // Failure is not fatal: the log keeps growing and the next
+            // notification retries.
+            match storage.compact(&counters, &self.idempotency.entries()) {
+                Ok(()) => tracing::debug!("compacted storage"),
+                Err(error) => tracing::warn!(%error, "failed to compact storage"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_must_be_safe_directory_names() {
+        for name in ["team-a", "TEAM_2", "default"] {
+            assert!(validate_name(name).is_ok(), "{name}");
+        }
+        for name in ["", "..", "a/b", "team a", &"x".repeat(65)] {
+            assert!(validate_name(name).is_err(), "{name}");
+        }
+        let root = Path::new("/data");
+        assert_eq!(data_dir(root, "default").unwrap(), root);
+        assert_eq!(
+            data_dir(root, "team-a").unwrap(),
+            Path::new("/data/namespaces/team-a")
+        );
+    }
+}
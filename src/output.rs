@@ -0,0 +1,231 @@
+//! Typed results returned by the counter tools.
+//!
+//! Every tool result carries the same JSON twice: as `structuredContent`,
+//! validated against the tool's output schema, and as a text block for
+//! clients that predate structured output.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use rmcp::{
+    ErrorData,
+    model::{CallToolResult, Content},
+    schemars::{self, JsonSchema},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::AuditEntry,
+    counter::{Counter, CounterKind, Quota},
+    crdt::ReplicatedCounter,
+    history::Operation,
+    rate::Window,
+};
+
+/// The state of one counter as seen by a tool call.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct CounterOutput {
+    /// Name of the counter.
+    pub name: String,
+    pub kind: CounterKind,
+    /// Value after the call; the total number of events for a rate counter.
+    pub value: i64,
+    /// Value before the call; absent for a counter the call created.
+    pub previous_value: Option<i64>,
+    /// Number of writes the counter has accepted since it was created.
+    pub version: u64,
+    /// Lowest value the counter may hold, if bounded below.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<i64>,
+    /// Highest value the counter may hold, if bounded above.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<i64>,
+    /// Cap on how far the counter may rise per period, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<Quota>,
+    /// Amount counted against the quota in the current period.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota_used: Option<u64>,
+    /// When the server produced this result.
+    pub timestamp: DateTime<Utc>,
+}
+
+impl CounterOutput {
+    pub fn new(name: &str, previous_value: Option<i64>, counter: &Counter) -> Self {
+        let timestamp = Utc::now();
+        Self {
+            name: name.to_string(),
+            kind: counter.kind(),
+            value: counter.value,
+            previous_value,
+            version: counter.version,
+            min: counter.min,
+            max: counter.max,
+            quota: counter.quota,
+            quota_used: counter.quota_used(timestamp),
+            timestamp,
+        }
+    }
+
+    /// Output for a call that did not change the counter.
+    pub fn unchanged(name: &str, counter: &Counter) -> Self {
+        Self::new(name, Some(counter.value), counter)
+    }
+}
+
+/// Result of `list_counters`.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct CounterList {
+    pub counters: Vec<CounterOutput>,
+}
+
+/// Returned by `compare_and_set` when the counter did not match.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Conflict {
+    pub conflict: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_value: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_version: Option<u64>,
+    pub current: CounterOutput,
+}
+
+/// Result of `compare_and_set`: the updated counter, or a [`Conflict`] when
+/// it did not match.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(untagged)]
+#[schemars(extend("type" = "object"))]
+pub enum CompareAndSetOutput {
+    Set(CounterOutput),
+    Conflict(Conflict),
+}
+
+/// Result of `transaction`.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TransactionOutput {
+    /// State of the counter after each operation, in order.
+    pub results: Vec<CounterOutput>,
+}
+
+/// Returned by `transaction` when an `assert_equals` operation did not hold.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TransactionConflict {
+    pub conflict: bool,
+    /// Index of the failed assertion.
+    pub operation: usize,
+    /// The counter as the assertion saw it.
+    pub current: CounterOutput,
+}
+
+/// Result of `transaction`: the state after every operation, or a
+/// [`TransactionConflict`] when an assertion did not hold.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(untagged)]
+#[schemars(extend("type" = "object"))]
+pub enum TransactionResult {
+    Applied(TransactionOutput),
+    Conflict(TransactionConflict),
+}
+
+/// Returned by `undo` and `redo`.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Reverted {
+    #[serde(flatten)]
+    pub counter: CounterOutput,
+    /// The operation that was reverted or reapplied.
+    pub operation: Operation,
+}
+
+/// Result of `history`.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct HistoryOutput {
+    pub name: String,
+    /// Operations `undo` can revert, oldest first.
+    pub operations: Vec<Operation>,
+    /// Operations `redo` can reapply, next one first.
+    pub undone: Vec<Operation>,
+}
+
+/// Result of `merge`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MergeOutput {
+    /// Replica id of the server that merged.
+    pub replica: String,
+    /// Replica state of every counter the caller may read and that can be
+    /// replicated, after the merge, for the caller to merge in turn.
+    pub counters: BTreeMap<String, ReplicatedCounter>,
+    /// Names of the counters the merge changed.
+    pub updated: Vec<String>,
+}
+
+/// Result of `sync`.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct SyncOutput {
+    /// Name of the configured peer.
+    pub peer: String,
+    /// Replica id of the peer.
+    pub replica: String,
+    /// Number of counters sent to the peer.
+    pub sent: usize,
+    /// Number of counters the peer sent back.
+    pub received: usize,
+    /// Names of the counters the sync changed on the peer.
+    pub updated_on_peer: Vec<String>,
+    /// Local counters the sync changed.
+    pub updated: Vec<CounterOutput>,
+}
+
+/// Result of `query_audit`.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct AuditEntries {
+    pub entries: Vec<AuditEntry>,
+}
+
+/// Events a rate counter recorded in one window.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct WindowOutput {
+    pub name: String,
+    pub window: Window,
+    pub events: u64,
+    /// Start of the oldest bucket counted.
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Events and rate of a rate counter over one window.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct WindowRate {
+    pub window: Window,
+    pub events: u64,
+    /// Events per second, averaged over the window.
+    pub per_second: f64,
+}
+
+/// Result of `get_rate`.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RateOutput {
+    pub name: String,
+    /// Events recorded since the counter was created or last reset.
+    pub total: i64,
+    /// The last minute, hour and day, in that order.
+    pub windows: Vec<WindowRate>,
+}
+
+fn structured<T: Serialize>(output: &T, is_error: bool) -> Result<CallToolResult, ErrorData> {
+    let value = serde_json::to_value(output).map_err(|e| {
+        ErrorData::internal_error(format!("failed to serialize tool result: {e}"), None)
+    })?;
+    Ok(CallToolResult {
+        content: Some(vec![Content::text(value.to_string())]),
+        structured_content: Some(value),
+        is_error: Some(is_error),
+    })
+}
+
+pub fn success<T: Serialize>(output: &T) -> Result<CallToolResult, ErrorData> {
+    structured(output, false)
+}
+
+pub fn failure<T: Serialize>(output: &T) -> Result<CallToolResult, ErrorData> {
+    structured(output, true)
+}
@@ -0,0 +1,61 @@
+//! Client side of the `sync` tool: calls `merge` on a configured peer over
+//! streamable HTTP.
+
+use std::{collections::BTreeMap, error::Error, time::Duration};
+
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderValue};
+use rmcp::{
+    ServiceExt,
+    model::CallToolRequestParam,
+    transport::{
+        StreamableHttpClientTransport, streamable_http_client::StreamableHttpClientTransportConfig,
+    },
+};
+
+use crate::{
+    config::PeerConfig, crdt::ReplicatedCounter, namespace::NAMESPACE_HEADER, output::MergeOutput,
+};
+
+/// How long a sync may take, connecting included, before it is abandoned.
+pub(crate) const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sends `counters` to the namespace `namespace` of `peer` and returns the
+/// peer's state after it merged them.
+pub(crate) async fn exchange(
+    peer: &PeerConfig,
+    namespace: &str,
+    counters: &BTreeMap<String, ReplicatedCounter>,
+) -> Result<MergeOutput, Box<dyn Error + Send + Sync>> {
+    let mut headers = HeaderMap::new();
+    headers.insert(NAMESPACE_HEADER, HeaderValue::from_str(namespace)?);
+    if let Some(token) = &peer.token {
+        let mut value = HeaderValue::from_str(&format!("Bearer {token}"))?;
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+    let client = reqwest::Client::builder()
+        .default_headers(headers)
+        .build()?;
+    let transport = StreamableHttpClientTransport::with_client(
+        client,
+        StreamableHttpClientTransportConfig::with_uri(peer.url.as_str()),
+    );
+    let service = ().serve(transport).await?;
+    let arguments = serde_json::json!({ "counters": counters });
+    let result = service
+        .call_tool(CallToolRequestParam {
+            name: "merge".into(),
+            arguments: arguments.as_object().cloned(),
+        })
+        .await;
+    // The session is only needed for this one call.
+    let _ = service.cancel().await;
+    let result = result?;
+    if result.is_error == Some(true) {
+        return Err("the peer refused the merge".into());
+    }
+    let output = result
+        .structured_content
+        .ok_or("the peer returned no structured result")?;
+    Ok(serde_json::from_value(output)?)
+}
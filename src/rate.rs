@@ -0,0 +1,161 @@
+//! Event counts over sliding windows, backing rate counters.
+//!
+//! Events are kept in one-second buckets for the last minute, one-minute
+//! buckets for the last hour and one-hour buckets for the last day. A window
+//! is therefore exact to the width of its buckets, and a counter holds at
+//! most 144 buckets however many events it records.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use rmcp::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+/// A sliding window ending now.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Window {
+    /// The last 60 seconds.
+    #[default]
+    Minute,
+    /// The last 60 minutes.
+    Hour,
+    /// The last 24 hours.
+    Day,
+}
+
+impl Window {
+    pub const ALL: [Self; 3] = [Self::Minute, Self::Hour, Self::Day];
+
+    /// Length of the window in seconds.
+    pub fn seconds(self) -> i64 {
+        match self {
+            Self::Minute => 60,
+            Self::Hour => 60 * 60,
+            Self::Day => 24 * 60 * 60,
+        }
+    }
+
+    /// Width in seconds of the buckets the window is counted in.
+    fn bucket_width(self) -> i64 {
+        match self {
+            Self::Minute => 1,
+            Self::Hour => 60,
+            Self::Day => 60 * 60,
+        }
+    }
+
+    /// Start of the oldest bucket in the window ending at `now`.
+    fn first_bucket(self, now: i64) -> i64 {
+        let width = self.bucket_width();
+        now - now.rem_euclid(width) - self.seconds() + width
+    }
+}
+
+/// Recent events of a rate counter.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateWindows {
+    /// Events per bucket of each window, keyed by the bucket's start in Unix
+    /// seconds.
+    seconds: BTreeMap<i64, u64>,
+    minutes: BTreeMap<i64, u64>,
+    hours: BTreeMap<i64, u64>,
+}
+
+impl RateWindows {
+    fn buckets(&self, window: Window) -> &BTreeMap<i64, u64> {
+        match window {
+            Window::Minute => &self.seconds,
+            Window::Hour => &self.minutes,
+            Window::Day => &self.hours,
+        }
+    }
+
+    fn buckets_mut(&mut self, window: Window) -> &mut BTreeMap<i64, u64> {
+        match window {
+            Window::Minute => &mut self.seconds,
+            Window::Hour => &mut self.minutes,
+            Window::Day => &mut self.hours,
+        }
+    }
+
+    /// Records `events` events at `at`, forgetting buckets that have left
+    /// their window. Bucket counts saturate at `u64::MAX`.
+    pub fn record(&mut self, at: DateTime<Utc>, events: u64) {
+        let now = at.timestamp();
+        for window in Window::ALL {
+            let first = window.first_bucket(now);
+            let buckets = self.buckets_mut(window);
+            let bucket = now - now.rem_euclid(window.bucket_width());
+            let count = buckets.entry(bucket).or_default();
+            *count = count.saturating_add(events);
+            *buckets = buckets.split_off(&first);
+        }
+    }
+
+    /// Number of events in `window` ending at `now`, saturating at
+    /// `u64::MAX`.
+    pub fn count(&self, window: Window, now: DateTime<Utc>) -> u64 {
+        let first = window.first_bucket(now.timestamp());
+        self.buckets(window)
+            .range(first..)
+            .map(|(_, n)| *n)
+            .fold(0, u64::saturating_add)
+    }
+
+    /// When the oldest bucket counted in `window` ending at `now` starts.
+    pub fn start(window: Window, now: DateTime<Utc>) -> DateTime<Utc> {
+        let first = window.first_bucket(now.timestamp());
+        DateTime::from_timestamp(first, 0).unwrap_or(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `seconds` after a midnight, so that buckets line up with the events.
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_699_920_000 + seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn windows_slide_at_their_bucket_width() {
+        let mut rate = RateWindows::default();
+        rate.record(at(0), 2);
+        rate.record(at(30), 1);
+        rate.record(at(90), 4);
+
+        assert_eq!(rate.count(Window::Minute, at(90)), 4);
+        assert_eq!(rate.count(Window::Minute, at(149)), 4);
+        assert_eq!(rate.count(Window::Minute, at(150)), 0);
+        assert_eq!(rate.count(Window::Hour, at(90)), 7);
+        assert_eq!(rate.count(Window::Day, at(90)), 7);
+        assert_eq!(rate.count(Window::Hour, at(2 * 60 * 60)), 0);
+        assert_eq!(rate.count(Window::Day, at(2 * 60 * 60)), 7);
+    }
+
+    #[test]
+    fn old_buckets_are_dropped() {
+        let mut rate = RateWindows::default();
+        for minute in 0..200 {
+            rate.record(at(minute * 60), 1);
+        }
+        assert_eq!(rate.seconds.len(), 1);
+        assert_eq!(rate.minutes.len(), 60);
+        assert!(rate.hours.len() <= 24);
+        assert_eq!(rate.count(Window::Day, at(199 * 60)), 200);
+    }
+
+    #[test]
+    fn counts_saturate_instead_of_overflowing() {
+        let mut rate = RateWindows::default();
+        for second in [0, 0, 0, 1] {
+            rate.record(at(second), i64::MAX as u64);
+        }
+        assert_eq!(rate.seconds[&at(0).timestamp()], u64::MAX);
+        assert_eq!(rate.count(Window::Minute, at(1)), u64::MAX);
+        assert_eq!(rate.count(Window::Day, at(1)), u64::MAX);
+    }
+}
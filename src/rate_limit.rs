@@ -0,0 +1,169 @@
+//! Token-bucket limits on how fast a session may call tools.
+//!
+//! Each session has one bucket for all of its calls and one per tool that
+//! has its own limit. A call is admitted only if every bucket it draws from
+//! holds a token, and then takes one from each.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A sustained rate with room for bursts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// Calls that may be made at once after a quiet period.
+    pub burst: u32,
+    /// Calls allowed per minute in the long run.
+    pub per_minute: u32,
+}
+
+/// Limits applied to every session.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    /// Limit on all calls a session makes.
+    pub session: Option<RateLimit>,
+    /// Limits on calls to individual tools, by tool name.
+    pub tools: BTreeMap<String, RateLimit>,
+}
+
+/// Which limit rejected a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Session,
+    Tool,
+}
+
+/// A call that exceeded a limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exceeded {
+    pub scope: Scope,
+    pub limit: RateLimit,
+    /// How long until the call would be admitted.
+    pub retry_after: Duration,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        }
+    }
+
+    /// Adds the tokens earned since the last update, then returns how long
+    /// until one is available.
+    fn wait(&mut self, limit: RateLimit, now: Instant) -> Option<Duration> {
+        let per_second = f64::from(limit.per_minute) / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(f64::from(limit.burst));
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            return None;
+        }
+        // A zero rate never refills; report the longest wait we can.
+        Some(Duration::try_from_secs_f64((1.0 - self.tokens) / per_second).unwrap_or(Duration::MAX))
+    }
+}
+
+/// The buckets of one session.
+#[derive(Debug, Default)]
+pub struct SessionLimiter {
+    session: Option<TokenBucket>,
+    tools: HashMap<String, TokenBucket>,
+}
+
+impl SessionLimiter {
+    /// Takes a token for a call to `tool`, or reports the limit it exceeds
+    /// and takes nothing.
+    pub fn admit(&mut self, limits: &RateLimits, tool: &str) -> Result<(), Exceeded> {
+        let now = Instant::now();
+        let mut buckets = Vec::with_capacity(2);
+        if let Some(limit) = limits.session {
+            let bucket = self
+                .session
+                .get_or_insert_with(|| TokenBucket::full(limit, now));
+            buckets.push((Scope::Session, limit, bucket));
+        }
+        if let Some(&limit) = limits.tools.get(tool) {
+            let bucket = self
+                .tools
+                .entry(tool.to_string())
+                .or_insert_with(|| TokenBucket::full(limit, now));
+            buckets.push((Scope::Tool, limit, bucket));
+        }
+        for (scope, limit, bucket) in &mut buckets {
+            if let Some(retry_after) = bucket.wait(*limit, now) {
+                return Err(Exceeded {
+                    scope: *scope,
+                    limit: *limit,
+                    retry_after,
+                });
+            }
+        }
+        for (_, _, bucket) in buckets {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bursts_then_refills() {
+        let limit = RateLimit {
+            burst: 2,
+            per_minute: 60,
+        };
+        let now = Instant::now();
+        let mut bucket = TokenBucket::full(limit, now);
+        for _ in 0..2 {
+            assert_eq!(bucket.wait(limit, now), None);
+            bucket.tokens -= 1.0;
+        }
+        let wait = bucket.wait(limit, now).unwrap();
+        assert!(wait > Duration::from_millis(990) && wait <= Duration::from_secs(1));
+        assert_eq!(bucket.wait(limit, now + Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn a_rejected_call_takes_no_tokens() {
+        let limits = RateLimits {
+            session: Some(RateLimit {
+                burst: 3,
+                per_minute: 0,
+            }),
+            tools: BTreeMap::from([(
+                "increment".to_string(),
+                RateLimit {
+                    burst: 1,
+                    per_minute: 0,
+                },
+            )]),
+        };
+        let mut limiter = SessionLimiter::default();
+        assert!(limiter.admit(&limits, "increment").is_ok());
+        let exceeded = limiter.admit(&limits, "increment").unwrap_err();
+        assert_eq!(exceeded.scope, Scope::Tool);
+        // The session bucket still holds the token the rejected call did not
+        // take.
+        assert!(limiter.admit(&limits, "get_counter").is_ok());
+        assert!(limiter.admit(&limits, "get_counter").is_ok());
+        let exceeded = limiter.admit(&limits, "get_counter").unwrap_err();
+        assert_eq!(exceeded.scope, Scope::Session);
+    }
+}
@@ -0,0 +1,728 @@
+//! Leader-follower replication of the mutation log over TCP.
+//!
+//! A leader streams every committed mutation to its followers, which apply
+//! and persist it and only serve reads. Frames are JSON, one per line. A
+//! follower opens with a [`Frame::Hello`]. The leader answers with a
+//! [`Frame::Snapshot`] of each namespace, then a [`Frame::Mutations`] for
+//! every write it commits, and a [`Frame::Heartbeat`] whenever it is idle.
+//!
+//! Mutations carry a per-namespace sequence number. A follower skips those
+//! its snapshot already covers, and reconnects for a fresh snapshot if it
+//! sees a gap. A follower with a failover timeout takes over as leader once
+//! it has not heard from any leader for that long.
+//!
+//! Every frame carries a term, which grows by one with each takeover and is
+//! persisted so that it survives restarts. Both sides adopt the newer term
+//! of a connection. A follower refuses a stream from an older term, and a
+//! leader steps down once a follower tells it of a newer one, so a replaced
+//! leader that comes back cannot feed followers stale writes. A configured
+//! leader that also lists `leaders` checks them first and follows one that
+//! already leads instead of starting as a second leader, and so does a
+//! follower before it takes over. While leading, it keeps checking them.
+//! Two instances that took over at once lead the same term; the one with
+//! the lower replica id steps down.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::broadcast::{self, error::RecvError},
+    time::timeout,
+};
+
+use crate::{
+    auth::hash_token, counter::Counter, idempotency::IdempotentResult, namespace::Namespace,
+    wal::Mutation,
+};
+
+/// File in the data directory holding the newest replication term the
+/// instance has seen.
+pub const TERM_FILE: &str = "replication_term";
+
+/// How often an idle leader tells its followers it is still there.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long either side waits for a frame before giving up on the
+/// connection.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Pause between rounds of connection attempts by a follower.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Committed writes buffered for each follower. One that falls further
+/// behind is disconnected and catches up from a new snapshot.
+const LOG_CAPACITY: usize = 1024;
+
+/// What an instance does in replication.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Accepts writes and streams them to followers.
+    #[default]
+    Leader,
+    /// Applies a leader's writes and rejects its own.
+    Follower,
+}
+
+/// Replication settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplicationConfig {
+    /// Role the instance starts in.
+    pub role: Role,
+    /// Address to stream the mutation log on while leading. A leader
+    /// without one has no followers.
+    pub bind: Option<SocketAddr>,
+    /// Replication addresses of the instances a follower follows, tried in
+    /// turn. A leader checks them on startup and follows one that already
+    /// leads instead.
+    pub leaders: Vec<SocketAddr>,
+    /// Seconds a follower goes without hearing from a leader before it takes
+    /// over. It never does when absent.
+    pub failover_secs: Option<u64>,
+    /// Shared secret followers must present to the leader.
+    pub token: Option<String>,
+    /// File keeping the newest term across restarts. The term is only held
+    /// in memory when absent.
+    pub term_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Frame {
+    /// Opens a connection. `term` is the newest term the sender has seen,
+    /// and `leader` its replica id if it leads that term itself.
+    Hello {
+        token: Option<String>,
+        term: u64,
+        leader: Option<String>,
+    },
+    Refused {
+        reason: String,
+        term: u64,
+    },
+    /// Full state of a namespace as of sequence number `seq`.
+    Snapshot {
+        term: u64,
+        namespace: String,
+        seq: u64,
+        counters: BTreeMap<String, Counter>,
+        idempotency: BTreeMap<String, IdempotentResult>,
+    },
+    /// The mutations of one write, which brought a namespace to `seq`.
+    Mutations {
+        term: u64,
+        namespace: String,
+        seq: u64,
+        mutations: Vec<Mutation>,
+    },
+    Heartbeat {
+        term: u64,
+    },
+}
+
+impl Frame {
+    /// The sender's term.
+    fn term(&self) -> u64 {
+        match self {
+            Self::Hello { term, .. }
+            | Self::Refused { term, .. }
+            | Self::Snapshot { term, .. }
+            | Self::Mutations { term, .. }
+            | Self::Heartbeat { term } => *term,
+        }
+    }
+
+    /// Whether the sender is leading.
+    fn is_from_leader(&self) -> bool {
+        matches!(
+            self,
+            Self::Snapshot { .. } | Self::Mutations { .. } | Self::Heartbeat { .. }
+        )
+    }
+}
+
+/// Sequence number a follower has reached in each namespace.
+#[derive(Debug, Default)]
+struct Cursor(BTreeMap<String, u64>);
+
+impl Cursor {
+    fn reset(&mut self, namespace: &str, seq: u64) {
+        self.0.insert(namespace.to_string(), seq);
+    }
+
+    /// Whether the mutations that bring `namespace` to `seq` are due. Fails
+    /// if some were missed.
+    fn advance(&mut self, namespace: &str, seq: u64) -> io::Result<bool> {
+        let Some(last) = self.0.get_mut(namespace) else {
+            return Ok(false);
+        };
+        if seq <= *last {
+            return Ok(false);
+        }
+        if seq != *last + 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("missed mutations in namespace '{namespace}'"),
+            ));
+        }
+        *last = seq;
+        Ok(true)
+    }
+}
+
+/// Replication state of a server.
+pub(crate) struct Replication {
+    config: ReplicationConfig,
+    /// Replica id of this instance, which settles ties between leaders.
+    replica: String,
+    leading: AtomicBool,
+    /// Newest term seen.
+    term: Mutex<u64>,
+    /// Leader currently followed.
+    leader: Mutex<Option<SocketAddr>>,
+    /// Where the log is streamed while leading.
+    addr: OnceLock<SocketAddr>,
+    /// The namespaces replicated, known once started.
+    namespaces: OnceLock<Arc<BTreeMap<String, Arc<Namespace>>>>,
+    /// Serialized [`Frame::Mutations`] of every committed write.
+    log: broadcast::Sender<Arc<str>>,
+}
+
+impl Replication {
+    /// Fails if the term file cannot be read.
+    pub(crate) fn new(config: ReplicationConfig, replica: String) -> io::Result<Self> {
+        let term = match &config.term_file {
+            Some(path) => read_term(path)?,
+            None => 0,
+        };
+        Ok(Self {
+            // A leader that has others to check with only leads once it
+            // found none of them leading.
+            leading: AtomicBool::new(config.role == Role::Leader && config.leaders.is_empty()),
+            config,
+            replica,
+            term: Mutex::new(term),
+            leader: Mutex::new(None),
+            addr: OnceLock::new(),
+            namespaces: OnceLock::new(),
+            log: broadcast::channel(LOG_CAPACITY).0,
+        })
+    }
+
+    /// Whether the server accepts writes.
+    pub(crate) fn is_leading(&self) -> bool {
+        self.leading.load(Ordering::Acquire)
+    }
+
+    /// Replication address of the leader being followed, if connected.
+    pub(crate) fn leader(&self) -> Option<SocketAddr> {
+        *self.leader.lock().expect("replication lock poisoned")
+    }
+
+    /// Address the log is streamed on.
+    pub(crate) fn addr(&self) -> Option<SocketAddr> {
+        self.addr.get().copied()
+    }
+
+    /// The newest term seen.
+    pub(crate) fn term(&self) -> u64 {
+        *self.term.lock().expect("replication lock poisoned")
+    }
+
+    /// Moves to `term` if it is newer than the current one, and returns the
+    /// current term afterwards. A leader that learns of a newer term steps
+    /// down, since another instance took over.
+    fn observe(self: &Arc<Self>, term: u64) -> u64 {
+        {
+            let mut current = self.term.lock().expect("replication lock poisoned");
+            if term <= *current {
+                return *current;
+            }
+            // Fencing matters more than durability here: adopt the term even
+            // if it cannot be written.
+            if let Err(error) = self.store_term(term) {
+                tracing::error!(%error, "failed to persist the replication term");
+            }
+            *current = term;
+        }
+        self.step_down(term);
+        term
+    }
+
+    /// Stops accepting writes after another instance took over in `term`,
+    /// and follows the configured leaders if there are any.
+    fn step_down(self: &Arc<Self>, term: u64) {
+        if !self.leading.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        tracing::warn!(term, "another instance leads; stepping down");
+        if !self.config.leaders.is_empty()
+            && let Some(namespaces) = self.namespaces.get()
+        {
+            tokio::spawn(self.clone().follow(namespaces.clone()));
+        }
+    }
+
+    /// The frame opening a connection to another instance.
+    fn hello(&self) -> Frame {
+        Frame::Hello {
+            token: self.config.token.clone(),
+            term: self.term(),
+            leader: self.is_leading().then(|| self.replica.clone()),
+        }
+    }
+
+    fn store_term(&self, term: u64) -> io::Result<()> {
+        let Some(path) = &self.config.term_file else {
+            return Ok(());
+        };
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, format!("{term}\n"))?;
+        File::open(&tmp)?.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Sends the committed `mutations` of `namespace` to every follower. Must
+    /// be called while holding the namespace's counters lock, so that
+    /// sequence numbers follow the order of writes.
+    pub(crate) fn publish(&self, namespace: &Namespace, mutations: &[Mutation]) {
+        let seq = namespace.replication_seq.fetch_add(1, Ordering::AcqRel) + 1;
+        if self.log.receiver_count() == 0 {
+            return;
+        }
+        let frame = Frame::Mutations {
+            term: self.term(),
+            namespace: namespace.name.clone(),
+            seq,
+            mutations: mutations.to_vec(),
+        };
+        match serde_json::to_string(&frame) {
+            Ok(line) => {
+                let _ = self.log.send(line.into());
+            }
+            Err(error) => tracing::error!(%error, "failed to serialize replicated mutations"),
+        }
+    }
+
+    /// Starts streaming the log of `namespaces` if leading with a bind
+    /// address, or following otherwise. A leader listing `leaders` first
+    /// checks whether one of them already leads. Must be called from within
+    /// a Tokio runtime if any of these applies.
+    pub(crate) fn start(
+        self: &Arc<Self>,
+        namespaces: Arc<BTreeMap<String, Arc<Namespace>>>,
+    ) -> io::Result<()> {
+        let _ = self.namespaces.set(namespaces.clone());
+        match self.config.role {
+            Role::Leader => {
+                if let Some(bind) = self.config.bind {
+                    let listener = std::net::TcpListener::bind(bind)?;
+                    listener.set_nonblocking(true)?;
+                    self.lead(TcpListener::from_std(listener)?, namespaces.clone())?;
+                }
+                if !self.config.leaders.is_empty() {
+                    tokio::spawn(self.clone().claim(namespaces));
+                }
+            }
+            Role::Follower => {
+                if self.config.leaders.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "a follower needs at least one leader address",
+                    ));
+                }
+                tokio::spawn(self.clone().follow(namespaces));
+            }
+        }
+        Ok(())
+    }
+
+    fn lead(
+        self: &Arc<Self>,
+        listener: TcpListener,
+        namespaces: Arc<BTreeMap<String, Arc<Namespace>>>,
+    ) -> io::Result<()> {
+        let addr = listener.local_addr()?;
+        let _ = self.addr.set(addr);
+        tracing::info!(%addr, "streaming the mutation log to followers");
+        tokio::spawn(self.clone().serve(listener, namespaces));
+        Ok(())
+    }
+
+    async fn serve(
+        self: Arc<Self>,
+        listener: TcpListener,
+        namespaces: Arc<BTreeMap<String, Arc<Namespace>>>,
+    ) {
+        loop {
+            let (stream, follower) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    tracing::warn!(%error, "failed to accept a follower");
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            let replication = self.clone();
+            let namespaces = namespaces.clone();
+            tokio::spawn(async move {
+                tracing::info!(%follower, "follower connected");
+                if let Err(error) = replication.stream_to(stream, &namespaces).await {
+                    tracing::info!(%follower, %error, "follower disconnected");
+                }
+            });
+        }
+    }
+
+    async fn stream_to(
+        self: &Arc<Self>,
+        stream: TcpStream,
+        namespaces: &BTreeMap<String, Arc<Namespace>>,
+    ) -> io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let hello = timeout(IDLE_TIMEOUT, lines.next_line())
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        let Some(Frame::Hello {
+            token,
+            term: hello_term,
+            leader,
+        }) = hello.and_then(|line| serde_json::from_str(&line).ok())
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected a hello",
+            ));
+        };
+        if let Some(expected) = &self.config.token
+            && token.as_deref().map(hash_token) != Some(hash_token(expected))
+        {
+            let reason = "invalid replication token".to_string();
+            let term = self.term();
+            write_frame(&mut writer, &Frame::Refused { reason, term }).await?;
+            return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+        }
+        // A follower that has seen a newer term knows this instance was
+        // replaced.
+        let term = self.observe(hello_term);
+        if let Some(rival) = &leader
+            && hello_term == term
+        {
+            // An instance checking its own address is not a rival.
+            if *rival == self.replica {
+                let reason = "own address".to_string();
+                write_frame(&mut writer, &Frame::Refused { reason, term }).await?;
+                return Err(io::Error::other("connected to itself"));
+            }
+            // Both took over at once; the lower replica id gives way.
+            if *rival > self.replica {
+                self.step_down(term);
+            }
+        }
+        if !self.is_leading() {
+            let reason = "not leading".to_string();
+            write_frame(&mut writer, &Frame::Refused { reason, term }).await?;
+            return Err(io::Error::other("not leading"));
+        }
+
+        // Subscribing first means no write is lost between the snapshots and
+        // the stream; the follower skips those the snapshots cover.
+        let mut log = self.log.subscribe();
+        for namespace in namespaces.values() {
+            let frame = {
+                let counters = namespace.counters.lock().await;
+                Frame::Snapshot {
+                    term,
+                    namespace: namespace.name.clone(),
+                    seq: namespace.replication_seq.load(Ordering::Acquire),
+                    counters: counters.clone(),
+                    idempotency: namespace.idempotency.entries(),
+                }
+            };
+            write_frame(&mut writer, &frame).await?;
+        }
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            tokio::select! {
+                line = log.recv() => match line {
+                    Ok(line) => write_line(&mut writer, &line).await?,
+                    Err(RecvError::Lagged(_)) => {
+                        return Err(io::Error::other("the follower fell behind"));
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                _ = heartbeat.tick() => {
+                    if !self.is_leading() {
+                        return Err(io::Error::other("stepped down"));
+                    }
+                    let term = self.term();
+                    write_frame(&mut writer, &Frame::Heartbeat { term }).await?;
+                }
+            }
+        }
+    }
+
+    async fn follow(self: Arc<Self>, namespaces: Arc<BTreeMap<String, Arc<Namespace>>>) {
+        let failover = self.config.failover_secs.map(Duration::from_secs);
+        let mut last_contact = Instant::now();
+        loop {
+            for &leader in &self.config.leaders {
+                match self
+                    .follow_leader(leader, &namespaces, &mut last_contact)
+                    .await
+                {
+                    Ok(()) => tracing::warn!(%leader, "leader closed the replication stream"),
+                    Err(error) => tracing::debug!(%leader, %error, "cannot follow leader"),
+                }
+                *self.leader.lock().expect("replication lock poisoned") = None;
+            }
+            // Another follower may have taken over already.
+            if failover.is_some_and(|failover| last_contact.elapsed() >= failover)
+                && self.find_leader().await.is_none()
+                && self.clone().promote(namespaces.clone()).await
+            {
+                return;
+            }
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+    }
+
+    /// Applies the log streamed by `leader` until the connection ends.
+    async fn follow_leader(
+        self: &Arc<Self>,
+        leader: SocketAddr,
+        namespaces: &BTreeMap<String, Arc<Namespace>>,
+        last_contact: &mut Instant,
+    ) -> io::Result<()> {
+        let stream = timeout(IDLE_TIMEOUT, TcpStream::connect(leader))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        let (reader, mut writer) = stream.into_split();
+        write_frame(&mut writer, &self.hello()).await?;
+        let mut lines = BufReader::new(reader).lines();
+        let mut cursor = Cursor::default();
+        loop {
+            let line = timeout(IDLE_TIMEOUT, lines.next_line())
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+            let Some(line) = line else {
+                return Ok(());
+            };
+            let frame: Frame = serde_json::from_str(&line)?;
+            // A leader of an older term was replaced and must not be followed.
+            let term = frame.term();
+            if term < self.observe(term) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("the leader is still on term {term}"),
+                ));
+            }
+            *last_contact = Instant::now();
+            match frame {
+                Frame::Snapshot {
+                    namespace,
+                    seq,
+                    counters,
+                    idempotency,
+                    ..
+                } => {
+                    cursor.reset(&namespace, seq);
+                    match namespaces.get(&namespace) {
+                        Some(namespace) => namespace.replace(counters, idempotency).await?,
+                        None => tracing::warn!(namespace, "leader has an unknown namespace"),
+                    }
+                    let mut following = self.leader.lock().expect("replication lock poisoned");
+                    if following.replace(leader).is_none() {
+                        tracing::info!(%leader, "following leader");
+                    }
+                }
+                Frame::Mutations {
+                    namespace,
+                    seq,
+                    mutations,
+                    ..
+                } => {
+                    if cursor.advance(&namespace, seq)?
+                        && let Some(namespace) = namespaces.get(&namespace)
+                    {
+                        namespace.apply(mutations).await?;
+                    }
+                }
+                Frame::Heartbeat { .. } => {}
+                Frame::Refused { reason, .. } => {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, reason));
+                }
+                Frame::Hello { .. } => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected hello from the leader",
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Leads unless one of the configured `leaders` already leads a term at
+    /// least as new as this instance's, which it then follows.
+    async fn claim(self: Arc<Self>, namespaces: Arc<BTreeMap<String, Arc<Namespace>>>) {
+        if let Some(peer) = self.find_leader().await {
+            tracing::info!(%peer, "another instance already leads; following it");
+            return self.follow(namespaces).await;
+        }
+        if !self.clone().promote(namespaces.clone()).await {
+            self.follow(namespaces).await;
+        }
+    }
+
+    /// While leading `term`, checks the configured `leaders` for another
+    /// instance leading it or a newer one, and steps down if there is.
+    async fn watch(self: Arc<Self>, term: u64) {
+        loop {
+            tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+            if !self.is_leading() || self.term() != term {
+                return;
+            }
+            if let Some(peer) = self.find_leader().await {
+                tracing::warn!(%peer, term, "found another leader");
+                return self.step_down(self.term());
+            }
+        }
+    }
+
+    /// The first of the configured `leaders` that leads a term at least as
+    /// new as this instance's, if any.
+    async fn find_leader(self: &Arc<Self>) -> Option<SocketAddr> {
+        for &peer in &self.config.leaders {
+            match self.probe(peer).await {
+                Ok(true) => return Some(peer),
+                Ok(false) => {}
+                Err(error) => tracing::debug!(%peer, %error, "cannot reach replication peer"),
+            }
+        }
+        None
+    }
+
+    /// Says hello to `peer` and returns whether it leads the current term,
+    /// adopting a newer one it reports.
+    async fn probe(self: &Arc<Self>, peer: SocketAddr) -> io::Result<bool> {
+        let stream = timeout(IDLE_TIMEOUT, TcpStream::connect(peer))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        let (reader, mut writer) = stream.into_split();
+        write_frame(&mut writer, &self.hello()).await?;
+        let line = timeout(IDLE_TIMEOUT, BufReader::new(reader).lines().next_line())
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        let frame: Frame = serde_json::from_str(&line)?;
+        let term = frame.term();
+        Ok(frame.is_from_leader() && term >= self.observe(term))
+    }
+
+    /// Starts a new term and takes over as leader, streaming the log to
+    /// followers if a bind address is configured. Returns false, leaving the
+    /// instance a follower, if the new term cannot be persisted.
+    async fn promote(self: Arc<Self>, namespaces: Arc<BTreeMap<String, Arc<Namespace>>>) -> bool {
+        let term = {
+            let mut term = self.term.lock().expect("replication lock poisoned");
+            if let Err(error) = self.store_term(*term + 1) {
+                tracing::error!(%error, "failed to persist the replication term");
+                return false;
+            }
+            *term += 1;
+            *term
+        };
+        tracing::warn!(term, "no leader reachable; taking over as leader");
+        self.leading.store(true, Ordering::Release);
+        if !self.config.leaders.is_empty() {
+            tokio::spawn(self.clone().watch(term));
+        }
+        // An instance that led before still listens.
+        if self.addr().is_some() {
+            return true;
+        }
+        let Some(bind) = self.config.bind else {
+            return true;
+        };
+        let listening = match TcpListener::bind(bind).await {
+            Ok(listener) => self.lead(listener, namespaces),
+            Err(error) => Err(error),
+        };
+        if let Err(error) = listening {
+            tracing::error!(%bind, %error, "failed to stream the mutation log");
+        }
+        true
+    }
+}
+
+/// Reads the term stored at `path`; a missing file means no term yet.
+fn read_term(path: &Path) -> io::Result<u64> {
+    match fs::read_to_string(path) {
+        Ok(text) => text.trim().parse().map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid replication term in {}: {error}", path.display()),
+            )
+        }),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(error) => Err(error),
+    }
+}
+
+async fn write_frame(writer: &mut (impl AsyncWrite + Unpin), frame: &Frame) -> io::Result<()> {
+    write_line(writer, &serde_json::to_string(frame)?).await
+}
+
+async fn write_line(writer: &mut (impl AsyncWrite + Unpin), line: &str) -> io::Result<()> {
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_skips_covered_writes_and_detects_gaps() {
+        let mut cursor = Cursor::default();
+        assert!(!cursor.advance("default", 1).unwrap());
+        cursor.reset("default", 5);
+        assert!(!cursor.advance("default", 4).unwrap());
+        assert!(!cursor.advance("default", 5).unwrap());
+        assert!(cursor.advance("default", 6).unwrap());
+        assert!(cursor.advance("default", 8).is_err());
+    }
+
+    #[tokio::test]
+    async fn newer_terms_are_adopted_and_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ReplicationConfig {
+            term_file: Some(dir.path().join(TERM_FILE)),
+            ..ReplicationConfig::default()
+        };
+        let replication = Arc::new(Replication::new(config.clone(), "a".to_string()).unwrap());
+        assert!(replication.is_leading());
+        assert_eq!(replication.observe(3), 3);
+        assert!(!replication.is_leading());
+        assert_eq!(replication.observe(2), 3);
+
+        let restarted = Replication::new(config, "a".to_string()).unwrap();
+        assert_eq!(restarted.term(), 3);
+    }
+}
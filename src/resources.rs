@@ -0,0 +1,79 @@
+//! Counters published as MCP resources, e.g. `counter://default`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use rmcp::{Peer, RoleServer, model::ResourceUpdatedNotificationParam};
+
+const SCHEME: &str = "counter://";
+
+pub const MIME_TYPE: &str = "application/json";
+
+pub fn counter_uri(name: &str) -> String {
+    format!("{SCHEME}{name}")
+}
+
+/// Returns the counter name a `counter://` URI refers to.
+pub fn counter_name(uri: &str) -> Option<&str> {
+    uri.strip_prefix(SCHEME).filter(|name| !name.is_empty())
+}
+
+struct Subscriber {
+    peer: Peer<RoleServer>,
+    uris: HashSet<String>,
+}
+
+/// Which session is subscribed to which counter URIs.
+#[derive(Clone, Default)]
+pub struct Subscriptions {
+    sessions: Arc<Mutex<HashMap<u64, Subscriber>>>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(&self, session: u64, peer: Peer<RoleServer>, uri: String) {
+        let mut sessions = self.sessions.lock().expect("subscriptions lock poisoned");
+        sessions
+            .entry(session)
+            .or_insert_with(|| Subscriber {
+                peer,
+                uris: HashSet::new(),
+            })
+            .uris
+            .insert(uri);
+    }
+
+    pub fn unsubscribe(&self, session: u64, uri: &str) {
+        let mut sessions = self.sessions.lock().expect("subscriptions lock poisoned");
+        if let Some(subscriber) = sessions.get_mut(&session) {
+            subscriber.uris.remove(uri);
+            if subscriber.uris.is_empty() {
+                sessions.remove(&session);
+            }
+        }
+    }
+
+    /// Sends `notifications/resources/updated` for every URI in `uris` to the
+    /// sessions subscribed to it, forgetting sessions whose transport closed.
+    pub async fn notify_updated(&self, uris: &[String]) {
+        let targets: Vec<_> = {
+            let mut sessions = self.sessions.lock().expect("subscriptions lock poisoned");
+            sessions.retain(|_, subscriber| !subscriber.peer.is_transport_closed());
+            sessions
+                .values()
+                .flat_map(|subscriber| {
+                    uris.iter()
+                        .filter(|uri| subscriber.uris.contains(*uri))
+                        .map(|uri| (subscriber.peer.clone(), uri.clone()))
+                })
+                .collect()
+        };
+        for (peer, uri) in targets {
+            // A peer that went away in the meantime is pruned on the next call.
+            let _ = peer
+                .notify_resource_updated(ResourceUpdatedNotificationParam { uri })
+                .await;
+        }
+    }
+}
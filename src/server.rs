@@ -0,0 +1,1666 @@
+use chrono::Utc;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use rmcp::{
+    ErrorData, RoleServer, ServerHandler,
+    handler::server::tool::{Parameters, ToolCallContext, cached_schema_for_type},
+    model::{
+        AnnotateAble, CallToolRequestParam, CallToolResult, Implementation, InitializeRequestParam,
+        InitializeResult, JsonObject, ListResourcesResult, ListToolsResult, PaginatedRequestParam,
+        ProtocolVersion, RawResource, ReadResourceRequestParam, ReadResourceResult,
+        ResourceContents, ServerCapabilities, ServerInfo, SetLevelRequestParam,
+        SubscribeRequestParam, UnsubscribeRequestParam,
+    },
+    schemars::{self, JsonSchema},
+    service::RequestContext,
+    tool, tool_router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::Instrument;
+
+use crate::{
+    acl::{Acl, Permission},
+    audit::{AuditEntry, AuditLog, AuditQuery, ClientInfo, Outcome},
+    auth::{self, Principal},
+    builder::CounterServerBuilder,
+    config::PeerConfig,
+    counter::{Counter, CounterKind, OverflowPolicy, Quota},
+    crdt::{INITIAL_REPLICA, ReplicatedCounter},
+    error::{
+        access_denied, audit_disabled, counter_exists, counter_limit, counter_not_found,
+        default_undeletable, idempotency_key_reused, name_unavailable, non_positive_amount,
+        not_a_rate_counter, nothing_to, out_of_bounds, quota_exceeded, rate_counter, rate_limited,
+        read_only, step_refused, storage_error, sync_failed, unknown_namespace, unknown_peer,
+    },
+    history::{Actor, Change},
+    idempotency::{Idempotent, IdempotentResult},
+    logging::{self, ClientLogs},
+    metrics::{Metrics, SessionGuard},
+    namespace::{DEFAULT_NAMESPACE, NAMESPACE_HEADER, Namespace},
+    output::{
+        self, AuditEntries, CompareAndSetOutput, Conflict, CounterList, CounterOutput,
+        HistoryOutput, MergeOutput, RateOutput, Reverted, SyncOutput, TransactionConflict,
+        TransactionOutput, TransactionResult, WindowOutput, WindowRate,
+    },
+    peer::{self, SYNC_TIMEOUT},
+    rate::{RateWindows, Window},
+    rate_limit::{RateLimits, SessionLimiter},
+    replication::Replication,
+    resources::{self, counter_name, counter_uri},
+    wal::Mutation,
+};
+
+/// Name of the counter used when a tool call does not specify one.
+pub const DEFAULT_COUNTER: &str = "default";
+
+/// Instructions sent to clients on initialize unless the builder replaces them.
+pub const DEFAULT_INSTRUCTIONS: &str = "This server provide named counter tools. Use 'create_counter', 'delete_counter' and 'list_counters' to manage counters, and 'increment', 'decrement', 'set', 'reset', 'compare_and_set' and 'get_counter' to interact with one. 'increment' and 'decrement' accept an optional 'amount'. 'transaction' applies several operations across counters atomically. 'undo' and 'redo' step through a counter's recent operations, which 'history' lists. 'query_audit' searches the log of past tool calls. Every tool reports the counter's value and version; pass the version to 'compare_and_set' to update a counter without losing concurrent writes. Counters may be bounded by 'min' and 'max'. A counter's 'acl' can limit which principals may read, increment, decrement, set or delete it. A counter created with kind 'rate' records each increment as events; 'get_window' and 'get_rate' report how many happened in the last minute, hour or day. Each counter is also published as the resource 'counter://<name>', which can be subscribed to for change notifications. Calls that omit 'name' use the 'default' counter. Pass an 'idempotency_key' to any tool that changes counters to make retrying it safe. 'sync' exchanges counters with a configured peer instance so that both converge on the sum of their changes; 'merge' is the tool it calls on the peer.";
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CounterArgs {
+    /// Name of the counter; defaults to "default" when omitted.
+    name: Option<String>,
+}
+
+impl CounterArgs {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(DEFAULT_COUNTER)
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct WindowArgs {
+    /// Name of the rate counter; defaults to "default" when omitted.
+    name: Option<String>,
+    /// "minute" (default), "hour" or "day".
+    #[serde(default)]
+    window: Window,
+}
+
+impl WindowArgs {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(DEFAULT_COUNTER)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WriteArgs {
+    /// Name of the counter; defaults to "default" when omitted.
+    name: Option<String>,
+    /// Makes the call safe to retry: a later call with the same key returns
+    /// the original result without changing anything again.
+    idempotency_key: Option<String>,
+}
+
+impl WriteArgs {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(DEFAULT_COUNTER)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StepArgs {
+    /// Name of the counter; defaults to "default" when omitted.
+    name: Option<String>,
+    /// How far to move the counter, which must be positive; defaults to 1.
+    #[schemars(range(min = 1))]
+    amount: Option<i64>,
+    /// Makes the call safe to retry: a later call with the same key returns
+    /// the original result without changing anything again.
+    idempotency_key: Option<String>,
+}
+
+impl StepArgs {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(DEFAULT_COUNTER)
+    }
+
+    fn amount(&self) -> Result<i64, ErrorData> {
+        positive(self.amount.unwrap_or(1))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SetArgs {
+    /// Name of the counter; defaults to "default" when omitted.
+    name: Option<String>,
+    /// Value to store in the counter.
+    value: i64,
+    /// Makes the call safe to retry: a later call with the same key returns
+    /// the original result without changing anything again.
+    idempotency_key: Option<String>,
+}
+
+impl SetArgs {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(DEFAULT_COUNTER)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CompareAndSetArgs {
+    /// Name of the counter; defaults to "default" when omitted.
+    name: Option<String>,
+    /// Only set the counter if it currently holds this value.
+    expected_value: Option<i64>,
+    /// Only set the counter if it is currently at this version.
+    expected_version: Option<u64>,
+    /// Value to store in the counter.
+    value: i64,
+    /// Makes the call safe to retry: a later call with the same key returns
+    /// the original result without changing anything again.
+    idempotency_key: Option<String>,
+}
+
+impl CompareAndSetArgs {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(DEFAULT_COUNTER)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NamedCounterArgs {
+    /// Name of the counter.
+    name: String,
+    /// Makes the call safe to retry: a later call with the same key returns
+    /// the original result without changing anything again.
+    idempotency_key: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CreateCounterArgs {
+    /// Name of the counter.
+    name: String,
+    /// "standard" (default), or "rate" for a counter that records each
+    /// increment as events over sliding windows.
+    #[serde(default)]
+    kind: CounterKind,
+    /// Initial value; defaults to 0.
+    #[serde(default)]
+    value: i64,
+    /// Lowest value the counter may hold.
+    min: Option<i64>,
+    /// Highest value the counter may hold.
+    max: Option<i64>,
+    /// What to do when a step would leave the counter's bounds: "error"
+    /// (default), "saturate" (alias "clamp") or "wrap".
+    #[serde(default)]
+    overflow: OverflowPolicy,
+    /// Caps the total of increments per period.
+    quota: Option<Quota>,
+    /// Limits who may read, increment, decrement, set or delete the counter.
+    /// Each permission lists the principals granted it; omitted permissions
+    /// are open to everyone.
+    acl: Option<Acl>,
+    /// Makes the call safe to retry: a later call with the same key returns
+    /// the original result without changing anything again.
+    idempotency_key: Option<String>,
+}
+
+/// One step of a `transaction`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TransactionOp {
+    /// Add 'amount', which must be positive, or 1 when omitted.
+    Increment {
+        name: String,
+        #[schemars(range(min = 1))]
+        amount: Option<i64>,
+    },
+    /// Subtract 'amount', which must be positive, or 1 when omitted.
+    Decrement {
+        name: String,
+        #[schemars(range(min = 1))]
+        amount: Option<i64>,
+    },
+    /// Store 'value'.
+    Set { name: String, value: i64 },
+    /// Abort the transaction unless the counter holds 'value' at this point.
+    AssertEquals { name: String, value: i64 },
+}
+
+impl TransactionOp {
+    fn name(&self) -> &str {
+        match self {
+            Self::Increment { name, .. }
+            | Self::Decrement { name, .. }
+            | Self::Set { name, .. }
+            | Self::AssertEquals { name, .. } => name,
+        }
+    }
+
+    fn permission(&self) -> Permission {
+        match self {
+            Self::Increment { .. } => Permission::Increment,
+            Self::Decrement { .. } => Permission::Decrement,
+            Self::Set { .. } => Permission::Set,
+            Self::AssertEquals { .. } => Permission::Read,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TransactionArgs {
+    /// Operations applied in order; either all of them take effect or none.
+    operations: Vec<TransactionOp>,
+    /// Makes the call safe to retry: a later call with the same key returns
+    /// the original result without changing anything again.
+    idempotency_key: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MergeArgs {
+    /// Replica state of counters on the calling instance, by name.
+    counters: BTreeMap<String, ReplicatedCounter>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SyncArgs {
+    /// Name of a peer from the server's configuration.
+    peer: String,
+}
+
+macro_rules! impl_idempotent {
+    ($($args:ty),*) => {
+        $(impl Idempotent for $args {
+            fn idempotency_key(&self) -> Option<&str> {
+                self.idempotency_key.as_deref()
+            }
+        })*
+    };
+}
+
+impl_idempotent!(
+    WriteArgs,
+    StepArgs,
+    SetArgs,
+    CompareAndSetArgs,
+    NamedCounterArgs,
+    CreateCounterArgs,
+    TransactionArgs
+);
+
+// Merging the same state again changes nothing, so these need no key.
+impl Idempotent for MergeArgs {
+    fn idempotency_key(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl Idempotent for SyncArgs {
+    fn idempotency_key(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// An MCP server handler exposing named counters as tools and resources.
+///
+/// Cloning is cheap and every clone operates on the same counters. Use
+/// [`CounterServer::builder`] to configure persistence and namespaces, or
+/// [`CounterServer::new`] for a purely in-memory server.
+#[derive(Clone)]
+pub struct CounterServer {
+    /// Every hosted namespace by name, including [`DEFAULT_NAMESPACE`].
+    namespaces: Arc<BTreeMap<String, Arc<Namespace>>>,
+    /// Identifies this instance in counters' replica state.
+    replica: Arc<str>,
+    peers: Arc<BTreeMap<String, PeerConfig>>,
+    replication: Arc<Replication>,
+    rate_limits: Arc<RateLimits>,
+    instructions: Arc<str>,
+    /// Identifies the MCP session this handle serves; see [`CounterServer::new_session`].
+    session: u64,
+    info: Arc<SessionInfo>,
+    metrics: Metrics,
+    client_logs: ClientLogs,
+    audit: Option<AuditLog>,
+}
+
+/// What a server keeps about each session.
+#[derive(Default)]
+struct SessionInfo {
+    /// Client name and version.
+    client: OnceLock<Implementation>,
+    /// Counts the session as active until its last handle is dropped.
+    active: OnceLock<SessionGuard>,
+    limiter: std::sync::Mutex<SessionLimiter>,
+    /// Who the session authenticated as on initialize. Later requests must
+    /// come from the same principal.
+    principal: OnceLock<Option<Principal>>,
+    /// The namespace the session bound to on initialize.
+    namespace: OnceLock<Arc<Namespace>>,
+}
+
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(1);
+
+fn counter_mut<'a>(
+    counters: &'a mut BTreeMap<String, Counter>,
+    name: &str,
+) -> Result<&'a mut Counter, ErrorData> {
+    counters
+        .get_mut(name)
+        .ok_or_else(|| counter_not_found(name))
+}
+
+/// Permission a tool needs on the counter named in its arguments. Tools
+/// without one either touch no existing counter or check each counter
+/// themselves.
+fn tool_permission(tool: &str) -> Option<Permission> {
+    match tool {
+        "get_counter" | "get_window" | "get_rate" | "history" => Some(Permission::Read),
+        "increment" => Some(Permission::Increment),
+        "decrement" => Some(Permission::Decrement),
+        "set" | "reset" | "compare_and_set" | "undo" | "redo" => Some(Permission::Set),
+        "delete_counter" => Some(Permission::Delete),
+        _ => None,
+    }
+}
+
+/// The counters a call to `tool` with `arguments` touches, and the
+/// permission it needs on each. Arguments that do not parse yield nothing;
+/// the tool rejects them anyway.
+fn required_access(tool: &str, arguments: Option<&JsonObject>) -> Vec<(String, Permission)> {
+    if tool == "merge" {
+        let args = arguments.and_then(|arguments| {
+            serde_json::from_value::<MergeArgs>(Value::Object(arguments.clone())).ok()
+        });
+        return args.map_or_else(Vec::new, |args| {
+            args.counters
+                .into_keys()
+                .map(|name| (name, Permission::Set))
+                .collect()
+        });
+    }
+    if tool == "transaction" {
+        let args = arguments.and_then(|arguments| {
+            serde_json::from_value::<TransactionArgs>(Value::Object(arguments.clone())).ok()
+        });
+        return args.map_or_else(Vec::new, |args| {
+            args.operations
+                .iter()
+                .map(|op| (op.name().to_string(), op.permission()))
+                .collect()
+        });
+    }
+    let Some(permission) = tool_permission(tool) else {
+        return Vec::new();
+    };
+    let name = arguments
+        .and_then(|arguments| arguments.get("name"))
+        .and_then(Value::as_str)
+        .unwrap_or(DEFAULT_COUNTER);
+    vec![(name.to_string(), permission)]
+}
+
+/// Checks that `principal` has `permission` on the counter `name`. A
+/// counter the principal may not read is reported as missing, so that its
+/// existence is not revealed either.
+fn check_access(
+    counters: &BTreeMap<String, Counter>,
+    name: &str,
+    permission: Permission,
+    principal: Option<&Principal>,
+) -> Result<(), ErrorData> {
+    let Some(acl) = counters.get(name).and_then(|counter| counter.acl.as_ref()) else {
+        return Ok(());
+    };
+    if !acl.allows(Permission::Read, principal) {
+        return Err(counter_not_found(name));
+    }
+    if !acl.allows(permission, principal) {
+        return Err(access_denied(name, permission));
+    }
+    Ok(())
+}
+
+/// Whether `principal` may see `counter` in listings.
+fn visible(counter: &Counter, principal: Option<&Principal>) -> bool {
+    counter
+        .acl
+        .as_ref()
+        .is_none_or(|acl| acl.allows(Permission::Read, principal))
+}
+
+/// Replica state of the counters `principal` may read. Rate counters are
+/// not replicated.
+fn replicated(
+    counters: &BTreeMap<String, Counter>,
+    principal: Option<&Principal>,
+) -> BTreeMap<String, ReplicatedCounter> {
+    counters
+        .iter()
+        .filter(|(_, counter)| {
+            counter.kind() == CounterKind::Standard && visible(counter, principal)
+        })
+        .map(|(name, counter)| (name.clone(), counter.into()))
+        .collect()
+}
+
+/// Merges `states` into `counters`, creating the counters missing here.
+/// Rate counters and counters `principal` may not set are left alone. A sum
+/// that leaves a counter's bounds follows its overflow policy, and `replica`
+/// is credited with the adjustment. Returns the counters that changed.
+fn merge_states(
+    counters: &mut BTreeMap<String, Counter>,
+    states: &BTreeMap<String, ReplicatedCounter>,
+    replica: &str,
+    principal: Option<&Principal>,
+    max_counters: Option<usize>,
+) -> Result<Vec<CounterOutput>, ErrorData> {
+    let mut updated = Vec::new();
+    for (name, state) in states {
+        if let Some(counter) = counters.get_mut(name) {
+            let writable = counter.kind() == CounterKind::Standard
+                && counter.acl.as_ref().is_none_or(|acl| {
+                    acl.allows(Permission::Read, principal)
+                        && acl.allows(Permission::Set, principal)
+                });
+            let previous = counter.value;
+            if !writable {
+                continue;
+            }
+            let changed = counter
+                .merge(&state.replicas)
+                .map_err(|value| out_of_bounds(name, counter, value))?;
+            if changed {
+                // Record a clamp or wrap right away, so that the state sent
+                // back to the peer carries it and the peer does not adjust
+                // the counter a second time.
+                counter.reconcile(replica);
+                updated.push(CounterOutput::new(name, Some(previous), counter));
+            }
+            continue;
+        }
+        if let Some(max) = max_counters
+            && counters.len() >= max
+        {
+            return Err(counter_limit(max));
+        }
+        let counter = state.to_counter();
+        if !counter.contains(counter.value) {
+            return Err(out_of_bounds(name, &counter, counter.value));
+        }
+        updated.push(CounterOutput::new(name, None, &counter));
+        counters.insert(name.clone(), counter);
+    }
+    Ok(updated)
+}
+
+fn mutation_uri(mutation: &Mutation) -> Option<String> {
+    match mutation {
+        Mutation::Put { name, .. } | Mutation::Delete { name } => Some(counter_uri(name)),
+        Mutation::Remember { .. } => None,
+    }
+}
+
+fn invalid_uri(uri: &str) -> ErrorData {
+    ErrorData::invalid_params(
+        format!("'{uri}' is not a counter URI"),
+        Some(serde_json::json!({ "uri": uri })),
+    )
+}
+
+/// Tags an error raised by the operation at `index` of a transaction.
+fn at_operation(index: usize, mut error: ErrorData) -> ErrorData {
+    error.message = format!("operation {index}: {}", error.message).into();
+    match &mut error.data {
+        Some(serde_json::Value::Object(data)) => {
+            data.insert("operation".to_string(), index.into());
+        }
+        data @ None => *data = Some(serde_json::json!({ "operation": index })),
+        Some(_) => {}
+    }
+    error
+}
+
+/// Fails if `counter` is a rate counter, which `operation` does not apply to.
+fn standard_only(counter: &Counter, name: &str, operation: &str) -> Result<(), ErrorData> {
+    match counter.kind() {
+        CounterKind::Standard => Ok(()),
+        CounterKind::Rate => Err(rate_counter(name, operation)),
+    }
+}
+
+/// Counts raising `counter` by `amount` against its quota.
+fn charge_quota(counter: &mut Counter, name: &str, amount: u64) -> Result<(), ErrorData> {
+    let now = Utc::now();
+    counter.charge_quota(amount, now).map_err(|resets_at| {
+        let quota = counter.quota.expect("only a counter with a quota refuses");
+        let used = counter.quota_used(now).unwrap_or(0);
+        quota_exceeded(name, quota, used, amount, resets_at)
+    })
+}
+
+/// Charges `counter`'s quota for however far a write raised it above
+/// `previous`. Lowering a counter gives no quota back.
+fn charge_rise(counter: &mut Counter, name: &str, previous: i64) -> Result<(), ErrorData> {
+    match u64::try_from(i128::from(counter.value) - i128::from(previous)) {
+        Ok(0) | Err(_) => Ok(()),
+        Ok(rise) => charge_quota(counter, name, rise),
+    }
+}
+
+/// Checks that a step `amount` is positive. The tool or operation decides
+/// the direction, so that the permission it needs holds for the change.
+fn positive(amount: i64) -> Result<i64, ErrorData> {
+    if amount <= 0 {
+        return Err(non_positive_amount(amount));
+    }
+    Ok(amount)
+}
+
+/// Adds the positive `amount` to `counter`, charging its quota and, for a
+/// rate counter, recording that many events now.
+fn increment(counter: &mut Counter, name: &str, amount: i64) -> Result<(), ErrorData> {
+    let now = Utc::now();
+    charge_quota(counter, name, amount.unsigned_abs())?;
+    if counter.increment(amount).is_none() {
+        return Err(step_refused(name, "incrementing", counter, amount));
+    }
+    if let Some(rate) = &mut counter.rate {
+        rate.record(now, amount.unsigned_abs());
+    }
+    Ok(())
+}
+
+/// Applies one transaction step to `counters`. Returns `Ok(None)` if an
+/// assertion did not hold.
+fn apply_operation(
+    counters: &mut BTreeMap<String, Counter>,
+    op: &TransactionOp,
+) -> Result<Option<CounterOutput>, ErrorData> {
+    let name = op.name();
+    let counter = counter_mut(counters, name)?;
+    let previous = counter.value;
+    match *op {
+        TransactionOp::Increment { amount, .. } => {
+            increment(counter, name, positive(amount.unwrap_or(1))?)?;
+        }
+        TransactionOp::Decrement { amount, .. } => {
+            standard_only(counter, name, "decrement")?;
+            let amount = positive(amount.unwrap_or(1))?;
+            if counter.decrement(amount).is_none() {
+                return Err(step_refused(name, "decrementing", counter, amount));
+            }
+            charge_rise(counter, name, previous)?;
+        }
+        TransactionOp::Set { value, .. } => {
+            standard_only(counter, name, "set")?;
+            if !counter.contains(value) {
+                return Err(out_of_bounds(name, counter, value));
+            }
+            counter.set(value);
+            charge_rise(counter, name, previous)?;
+        }
+        TransactionOp::AssertEquals { value, .. } => {
+            if counter.value != value {
+                return Ok(None);
+            }
+        }
+    }
+    Ok(Some(CounterOutput::new(name, Some(previous), counter)))
+}
+
+/// Lists the mutations that turn `before` into `after`.
+fn diff(before: &BTreeMap<String, Counter>, after: &BTreeMap<String, Counter>) -> Vec<Mutation> {
+    let removed = before
+        .keys()
+        .filter(|name| !after.contains_key(*name))
+        .map(|name| Mutation::Delete { name: name.clone() });
+    let changed = after
+        .iter()
+        .filter(|(name, counter)| before.get(*name) != Some(counter))
+        .map(|(name, counter)| Mutation::Put {
+            name: name.clone(),
+            counter: counter.clone(),
+        });
+    removed.chain(changed).collect()
+}
+
+impl Default for CounterServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[tool_router(vis = "pub")]
+impl CounterServer {
+    /// Creates a server that keeps its counters in memory only.
+    pub fn new() -> Self {
+        Self::builder()
+            .build()
+            .expect("a server without storage or initial counters always builds")
+    }
+
+    pub fn builder() -> CounterServerBuilder {
+        CounterServerBuilder::default()
+    }
+
+    pub(crate) fn from_parts(
+        namespaces: BTreeMap<String, Arc<Namespace>>,
+        replica: String,
+        replication: Arc<Replication>,
+        builder: CounterServerBuilder,
+    ) -> Self {
+        Self {
+            namespaces: Arc::new(namespaces),
+            replica: replica.into(),
+            peers: Arc::new(builder.peers),
+            replication,
+            rate_limits: Arc::new(builder.rate_limits),
+            instructions: builder.instructions.into(),
+            session: 0,
+            info: Arc::default(),
+            metrics: Metrics::default(),
+            client_logs: builder.client_logs,
+            audit: builder.audit,
+        }
+    }
+
+    /// Returns a handle for a new MCP session. It shares every namespace with
+    /// `self` but keeps its own resource subscriptions, client identity and
+    /// namespace binding.
+    pub fn new_session(&self) -> Self {
+        Self {
+            session: NEXT_SESSION.fetch_add(1, Ordering::Relaxed),
+            info: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn start_replication(&self) -> std::io::Result<()> {
+        self.replication.start(self.namespaces.clone())
+    }
+
+    /// Whether the server accepts writes: it is not a replication follower,
+    /// or has taken over from its leader.
+    pub fn is_leader(&self) -> bool {
+        self.replication.is_leading()
+    }
+
+    /// Address the mutation log is streamed to followers on, once leading
+    /// with a replication bind address.
+    pub fn replication_addr(&self) -> Option<std::net::SocketAddr> {
+        self.replication.addr()
+    }
+
+    /// Fails on a replication follower, which only serves reads.
+    fn check_writable(&self) -> Result<(), ErrorData> {
+        match self.replication.is_leading() {
+            true => Ok(()),
+            false => Err(read_only(self.replication.leader())),
+        }
+    }
+
+    /// Renders counter values and server metrics in the Prometheus text
+    /// format; see [`crate::metrics`].
+    pub async fn render_metrics(&self) -> String {
+        let mut guards = Vec::with_capacity(self.namespaces.len());
+        for (name, namespace) in self.namespaces.iter() {
+            guards.push((name.as_str(), namespace.counters.lock().await));
+        }
+        let namespaces: Vec<_> = guards
+            .iter()
+            .map(|(name, counters)| (*name, &**counters))
+            .collect();
+        self.metrics.render(&namespaces)
+    }
+
+    /// The namespace the session is bound to. Handles that never saw an
+    /// `initialize`, such as a mounted [`CounterServer::tool_router`], use the
+    /// default namespace.
+    fn namespace(&self) -> &Arc<Namespace> {
+        self.info
+            .namespace
+            .get()
+            .unwrap_or_else(|| &self.namespaces[DEFAULT_NAMESPACE])
+    }
+
+    /// Picks the namespace a session initialized by `principal` binds to:
+    /// the one named by the [`NAMESPACE_HEADER`] header of an HTTP request,
+    /// or the default namespace.
+    fn resolve_namespace(
+        &self,
+        context: &RequestContext<RoleServer>,
+        principal: Option<&Principal>,
+    ) -> Result<Arc<Namespace>, ErrorData> {
+        let requested = context
+            .extensions
+            .get::<axum::http::request::Parts>()
+            .and_then(|parts| parts.headers.get(NAMESPACE_HEADER))
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| ErrorData::invalid_params("invalid namespace header", None))
+            })
+            .transpose()?
+            .unwrap_or(DEFAULT_NAMESPACE);
+        let namespace = self
+            .namespaces
+            .get(requested)
+            .ok_or_else(|| unknown_namespace(requested))?;
+        let allowed = namespace.principals.as_ref().is_none_or(|principals| {
+            principal.is_some_and(|principal| principals.contains(&principal.0))
+        });
+        // A namespace closed to the caller looks the same as a missing one.
+        if !allowed {
+            return Err(unknown_namespace(requested));
+        }
+        Ok(namespace.clone())
+    }
+
+    fn actor(&self) -> Actor {
+        Actor {
+            session: self.session,
+            client: self
+                .info
+                .client
+                .get()
+                .map(|info| format!("{} {}", info.name, info.version)),
+            principal: self.principal().map(|p| p.0.clone()),
+        }
+    }
+
+    /// Checks that a call to `tool` comes from the principal that started
+    /// the session and is within its rate limits.
+    fn admit(&self, tool: &str, principal: Option<&Principal>) -> Result<(), ErrorData> {
+        // Knowing a session's id must not let another token holder act in it.
+        if principal != self.principal() {
+            return Err(ErrorData::invalid_request(
+                "the session was started by another principal",
+                None,
+            ));
+        }
+        self.info
+            .limiter
+            .lock()
+            .expect("rate limiter lock poisoned")
+            .admit(&self.rate_limits, tool)
+            .map_err(|exceeded| rate_limited(tool, &exceeded))
+    }
+
+    fn principal(&self) -> Option<&Principal> {
+        self.info.principal.get().and_then(Option::as_ref)
+    }
+
+    /// Checks the ACL of every counter in `counters` that a call to `tool`
+    /// touches. Must be given the locked counters the call then works on, so
+    /// that an ACL installed in between cannot be bypassed.
+    fn authorize(
+        &self,
+        tool: &str,
+        arguments: Option<&JsonObject>,
+        counters: &BTreeMap<String, Counter>,
+    ) -> Result<(), ErrorData> {
+        let required = required_access(tool, arguments);
+        for (index, (name, permission)) in required.iter().enumerate() {
+            check_access(counters, name, *permission, self.principal()).map_err(|error| {
+                if tool == "transaction" {
+                    at_operation(index, error)
+                } else {
+                    error
+                }
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Applies `f` to a copy of the counters and, once the resulting changes
+    /// have been durably logged, makes it the live state, records `change` in
+    /// the history and notifies subscribers of every counter that changed. A
+    /// failed write leaves the in-memory counters untouched so that nothing
+    /// unsaved is acknowledged.
+    ///
+    /// Changes `f` makes to a counter's value are credited to this replica,
+    /// or to [`INITIAL_REPLICA`] for a counter `f` created. Committed
+    /// changes are streamed to replication followers, and a follower
+    /// rejects the call instead.
+    ///
+    /// The caller's access to the counters `args` names is checked first,
+    /// under the same lock.
+    ///
+    /// If `args` carries an idempotency key, the result is logged together
+    /// with the changes, and a later call with the same key returns it
+    /// without running `f` again.
+    async fn mutate(
+        &self,
+        change: Change,
+        args: &impl Idempotent,
+        f: impl FnOnce(&mut BTreeMap<String, Counter>) -> Result<CallToolResult, ErrorData>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let namespace = self.namespace();
+        let mut counters = namespace.counters.lock().await;
+        let arguments = serde_json::to_value(args)
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        self.authorize(change.tool(), arguments.as_object(), &counters)?;
+        let key = args.idempotency_key();
+        if let Some(key) = key
+            && let Some(previous) = namespace.idempotency.get(key)
+        {
+            if previous.tool != change.tool() || previous.arguments != arguments {
+                return Err(idempotency_key_reused(key));
+            }
+            return Ok(previous.result);
+        }
+        // Checked after the lookup, so that a retry reaching a follower still
+        // gets the result the leader remembered.
+        self.check_writable()?;
+
+        let mut next = counters.clone();
+        let result = f(&mut next)?;
+        for (name, counter) in next.iter_mut() {
+            let replica = match counters.contains_key(name) {
+                true => &*self.replica,
+                false => INITIAL_REPLICA,
+            };
+            counter.reconcile(replica);
+        }
+        let mut mutations = diff(&counters, &next);
+        let updated: Vec<_> = mutations.iter().filter_map(mutation_uri).collect();
+        let remembered = key.map(|key| {
+            (
+                key.to_string(),
+                IdempotentResult {
+                    tool: change.tool().to_string(),
+                    arguments,
+                    result: result.clone(),
+                    expires_at: namespace.idempotency.expiry(),
+                },
+            )
+        });
+        if let Some((key, entry)) = &remembered {
+            mutations.push(Mutation::Remember {
+                key: key.clone(),
+                entry: entry.clone(),
+            });
+        }
+        if mutations.is_empty() {
+            return Ok(result);
+        }
+        namespace.persist(mutations.clone()).map_err(|e| {
+            tracing::error!(error = %e, "failed to write mutations");
+            storage_error(e)
+        })?;
+        self.replication.publish(namespace, &mutations);
+        namespace
+            .history
+            .record(change, &counters, &mutations, &self.actor());
+        if let Some((key, entry)) = remembered {
+            namespace.idempotency.insert(key, entry);
+        }
+        tracing::debug!(mutations = mutations.len(), "applied {}", change.tool());
+        *counters = next;
+        drop(counters);
+        namespace.subscriptions.notify_updated(&updated).await;
+        Ok(result)
+    }
+
+    #[tool(
+        name = "increment",
+        description = "Tool that increments a counter by 'amount', or by 1 when omitted",
+        output_schema = cached_schema_for_type::<CounterOutput>()
+    )]
+    async fn increment(
+        &self,
+        Parameters(args): Parameters<StepArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let amount = args.amount()?;
+        self.mutate(Change::Apply("increment"), &args, |counters| {
+            let counter = counter_mut(counters, args.name())?;
+            let previous = counter.value;
+            increment(counter, args.name(), amount)?;
+            output::success(&CounterOutput::new(args.name(), Some(previous), counter))
+        })
+        .await
+    }
+
+    #[tool(
+        name = "decrement",
+        description = "Tool that decrements a counter by 'amount', or by 1 when omitted",
+        output_schema = cached_schema_for_type::<CounterOutput>()
+    )]
+    async fn decrement(
+        &self,
+        Parameters(args): Parameters<StepArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let amount = args.amount()?;
+        self.mutate(Change::Apply("decrement"), &args, |counters| {
+            let counter = counter_mut(counters, args.name())?;
+            standard_only(counter, args.name(), "decrement")?;
+            let previous = counter.value;
+            if counter.decrement(amount).is_none() {
+                return Err(step_refused(args.name(), "decrementing", counter, amount));
+            }
+            charge_rise(counter, args.name(), previous)?;
+            output::success(&CounterOutput::new(args.name(), Some(previous), counter))
+        })
+        .await
+    }
+
+    #[tool(
+        name = "get_counter",
+        description = "Tool that returns the current value and version of a counter",
+        output_schema = cached_schema_for_type::<CounterOutput>()
+    )]
+    async fn get_counter(
+        &self,
+        Parameters(args): Parameters<CounterArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let counters = self.namespace().counters.lock().await;
+        check_access(&counters, args.name(), Permission::Read, self.principal())?;
+        let counter = counters
+            .get(args.name())
+            .ok_or_else(|| counter_not_found(args.name()))?;
+        output::success(&CounterOutput::unchanged(args.name(), counter))
+    }
+
+    #[tool(
+        name = "get_window",
+        description = "Tool that counts the events a rate counter recorded in the last minute, hour or day",
+        output_schema = cached_schema_for_type::<WindowOutput>()
+    )]
+    async fn get_window(
+        &self,
+        Parameters(args): Parameters<WindowArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let counters = self.namespace().counters.lock().await;
+        check_access(&counters, args.name(), Permission::Read, self.principal())?;
+        let counter = counters
+            .get(args.name())
+            .ok_or_else(|| counter_not_found(args.name()))?;
+        let rate = counter
+            .rate
+            .as_ref()
+            .ok_or_else(|| not_a_rate_counter(args.name()))?;
+        let now = Utc::now();
+        output::success(&WindowOutput {
+            name: args.name().to_string(),
+            window: args.window,
+            events: rate.count(args.window, now),
+            start: RateWindows::start(args.window, now),
+            end: now,
+        })
+    }
+
+    #[tool(
+        name = "get_rate",
+        description = "Tool that reports how many events a rate counter recorded in the last minute, hour and day, and the average events per second over each",
+        output_schema = cached_schema_for_type::<RateOutput>()
+    )]
+    async fn get_rate(
+        &self,
+        Parameters(args): Parameters<CounterArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let counters = self.namespace().counters.lock().await;
+        check_access(&counters, args.name(), Permission::Read, self.principal())?;
+        let counter = counters
+            .get(args.name())
+            .ok_or_else(|| counter_not_found(args.name()))?;
+        let rate = counter
+            .rate
+            .as_ref()
+            .ok_or_else(|| not_a_rate_counter(args.name()))?;
+        let now = Utc::now();
+        let windows = Window::ALL
+            .into_iter()
+            .map(|window| {
+                let events = rate.count(window, now);
+                WindowRate {
+                    window,
+                    events,
+                    per_second: events as f64 / window.seconds() as f64,
+                }
+            })
+            .collect();
+        output::success(&RateOutput {
+            name: args.name().to_string(),
+            total: counter.value,
+            windows,
+        })
+    }
+
+    #[tool(
+        name = "set",
+        description = "Tool that sets a counter to a given value",
+        output_schema = cached_schema_for_type::<CounterOutput>()
+    )]
+    async fn set(
+        &self,
+        Parameters(args): Parameters<SetArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        self.mutate(Change::Apply("set"), &args, |counters| {
+            let counter = counter_mut(counters, args.name())?;
+            standard_only(counter, args.name(), "set")?;
+            if !counter.contains(args.value) {
+                return Err(out_of_bounds(args.name(), counter, args.value));
+            }
+            let previous = counter.value;
+            counter.set(args.value);
+            charge_rise(counter, args.name(), previous)?;
+            output::success(&CounterOutput::new(args.name(), Some(previous), counter))
+        })
+        .await
+    }
+
+    #[tool(
+        name = "reset",
+        description = "Tool that resets a counter to 0",
+        output_schema = cached_schema_for_type::<CounterOutput>()
+    )]
+    async fn reset(
+        &self,
+        Parameters(args): Parameters<WriteArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        self.mutate(Change::Apply("reset"), &args, |counters| {
+            let counter = counter_mut(counters, args.name())?;
+            if !counter.contains(0) {
+                return Err(out_of_bounds(args.name(), counter, 0));
+            }
+            let previous = counter.value;
+            counter.set(0);
+            charge_rise(counter, args.name(), previous)?;
+            if let Some(rate) = &mut counter.rate {
+                *rate = RateWindows::default();
+            }
+            output::success(&CounterOutput::new(args.name(), Some(previous), counter))
+        })
+        .await
+    }
+
+    #[tool(
+        name = "compare_and_set",
+        description = "Tool that sets a counter to 'value' only if it still has the expected value and/or version. On mismatch nothing is changed and the current state is returned as a conflict",
+        output_schema = cached_schema_for_type::<CompareAndSetOutput>()
+    )]
+    async fn compare_and_set(
+        &self,
+        Parameters(args): Parameters<CompareAndSetArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        if args.expected_value.is_none() && args.expected_version.is_none() {
+            return Err(ErrorData::invalid_params(
+                "either 'expected_value' or 'expected_version' is required",
+                None,
+            ));
+        }
+        self.mutate(Change::Apply("compare_and_set"), &args, |counters| {
+            let counter = counter_mut(counters, args.name())?;
+            standard_only(counter, args.name(), "compare_and_set")?;
+            let previous = counter.value;
+            let matches = args.expected_value.is_none_or(|v| v == counter.value)
+                && args.expected_version.is_none_or(|v| v == counter.version);
+            if !matches {
+                return output::failure(&CompareAndSetOutput::Conflict(Conflict {
+                    conflict: true,
+                    expected_value: args.expected_value,
+                    expected_version: args.expected_version,
+                    current: CounterOutput::unchanged(args.name(), counter),
+                }));
+            }
+            if !counter.contains(args.value) {
+                return Err(out_of_bounds(args.name(), counter, args.value));
+            }
+            counter.set(args.value);
+            charge_rise(counter, args.name(), previous)?;
+            output::success(&CompareAndSetOutput::Set(CounterOutput::new(
+                args.name(),
+                Some(previous),
+                counter,
+            )))
+        })
+        .await
+    }
+
+    #[tool(
+        name = "transaction",
+        description = "Tool that applies a list of operations (increment, decrement, set, assert_equals) across counters atomically: either every operation takes effect or none does. A failed assert_equals aborts the transaction and returns the counter's current state as a conflict",
+        output_schema = cached_schema_for_type::<TransactionResult>()
+    )]
+    async fn transaction(
+        &self,
+        Parameters(args): Parameters<TransactionArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        if args.operations.is_empty() {
+            return Err(ErrorData::invalid_params(
+                "a transaction needs at least one operation",
+                None,
+            ));
+        }
+        self.mutate(Change::Apply("transaction"), &args, |counters| {
+            let original = counters.clone();
+            let mut results = Vec::with_capacity(args.operations.len());
+            for (index, op) in args.operations.iter().enumerate() {
+                match apply_operation(counters, op).map_err(|e| at_operation(index, e))? {
+                    Some(output) => results.push(output),
+                    None => {
+                        let current = CounterOutput::unchanged(op.name(), &counters[op.name()]);
+                        // Roll back the earlier operations so that nothing
+                        // is written.
+                        *counters = original;
+                        return output::failure(&TransactionResult::Conflict(
+                            TransactionConflict {
+                                conflict: true,
+                                operation: index,
+                                current,
+                            },
+                        ));
+                    }
+                }
+            }
+            output::success(&TransactionResult::Applied(TransactionOutput { results }))
+        })
+        .await
+    }
+
+    #[tool(
+        name = "create_counter",
+        description = "Tool that creates a new named counter starting at 'value', or 0 when omitted, optionally bounded by 'min' and 'max'. With 'kind' set to 'rate' it instead counts events over sliding windows",
+        output_schema = cached_schema_for_type::<CounterOutput>()
+    )]
+    async fn create_counter(
+        &self,
+        Parameters(args): Parameters<CreateCounterArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        self.mutate(Change::Apply("create_counter"), &args, |counters| {
+            if let Some(existing) = counters.get(&args.name) {
+                // Saying it exists would reveal a counter the caller may not
+                // read.
+                return Err(match visible(existing, self.principal()) {
+                    true => counter_exists(&args.name),
+                    false => name_unavailable(&args.name),
+                });
+            }
+            if let Some(max) = self.namespace().limits.max_counters
+                && counters.len() >= max
+            {
+                return Err(counter_limit(max));
+            }
+            let counter = Counter {
+                value: args.value,
+                min: args.min,
+                max: args.max,
+                quota: args.quota,
+                acl: args.acl.clone(),
+                ..Counter::of_kind(args.kind, args.overflow)
+            };
+            counter
+                .validate()
+                .map_err(|e| ErrorData::invalid_params(e, None))?;
+            let output = CounterOutput::new(&args.name, None, &counter);
+            counters.insert(args.name.clone(), counter);
+            output::success(&output)
+        })
+        .await
+    }
+
+    #[tool(
+        name = "delete_counter",
+        description = "Tool that deletes a named counter and returns its last state",
+        output_schema = cached_schema_for_type::<CounterOutput>()
+    )]
+    async fn delete_counter(
+        &self,
+        Parameters(args): Parameters<NamedCounterArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        if args.name == DEFAULT_COUNTER {
+            return Err(default_undeletable());
+        }
+        self.mutate(Change::Apply("delete_counter"), &args, |counters| {
+            let counter = counters
+                .remove(&args.name)
+                .ok_or_else(|| counter_not_found(&args.name))?;
+            output::success(&CounterOutput::unchanged(&args.name, &counter))
+        })
+        .await
+    }
+
+    #[tool(
+        name = "list_counters",
+        description = "Tool that lists every counter the caller may read with its current value and version",
+        output_schema = cached_schema_for_type::<CounterList>()
+    )]
+    async fn list_counters(
+        &self,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let principal = auth::principal(&ctx);
+        let counters = self.namespace().counters.lock().await;
+        let counters = counters
+            .iter()
+            .filter(|(_, counter)| visible(counter, principal.as_ref()))
+            .map(|(name, counter)| CounterOutput::unchanged(name, counter))
+            .collect();
+        output::success(&CounterList { counters })
+    }
+
+    #[tool(
+        name = "undo",
+        description = "Tool that reverts the latest operation on a counter and returns the operation it reverted",
+        output_schema = cached_schema_for_type::<Reverted>()
+    )]
+    async fn undo(
+        &self,
+        Parameters(args): Parameters<WriteArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        self.mutate(Change::Undo, &args, |counters| {
+            let counter = counter_mut(counters, args.name())?;
+            standard_only(counter, args.name(), "undo")?;
+            let operation = self
+                .namespace()
+                .history
+                .last_done(args.name())
+                .ok_or_else(|| nothing_to("undo", args.name()))?;
+            let previous = counter.value;
+            counter.set(operation.previous_value);
+            charge_rise(counter, args.name(), previous)?;
+            output::success(&Reverted {
+                counter: CounterOutput::new(args.name(), Some(previous), counter),
+                operation,
+            })
+        })
+        .await
+    }
+
+    #[tool(
+        name = "redo",
+        description = "Tool that reapplies the operation most recently reverted by 'undo' and returns it",
+        output_schema = cached_schema_for_type::<Reverted>()
+    )]
+    async fn redo(
+        &self,
+        Parameters(args): Parameters<WriteArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        self.mutate(Change::Redo, &args, |counters| {
+            let counter = counter_mut(counters, args.name())?;
+            standard_only(counter, args.name(), "redo")?;
+            let operation = self
+                .namespace()
+                .history
+                .last_undone(args.name())
+                .ok_or_else(|| nothing_to("redo", args.name()))?;
+            let previous = counter.value;
+            counter.set(operation.value);
+            charge_rise(counter, args.name(), previous)?;
+            output::success(&Reverted {
+                counter: CounterOutput::new(args.name(), Some(previous), counter),
+                operation,
+            })
+        })
+        .await
+    }
+
+    #[tool(
+        name = "history",
+        description = "Tool that lists the recent operations on a counter, oldest first, with who made them and when, followed by the operations that 'redo' can reapply",
+        output_schema = cached_schema_for_type::<HistoryOutput>()
+    )]
+    async fn history(
+        &self,
+        Parameters(args): Parameters<CounterArgs>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let counters = self.namespace().counters.lock().await;
+        check_access(&counters, args.name(), Permission::Read, self.principal())?;
+        if !counters.contains_key(args.name()) {
+            return Err(counter_not_found(args.name()));
+        }
+        let (operations, undone) = self.namespace().history.get(args.name());
+        output::success(&HistoryOutput {
+            name: args.name().to_string(),
+            operations,
+            undone,
+        })
+    }
+
+    #[tool(
+        name = "merge",
+        description = "Tool that merges the replica state of counters sent by another instance, creating those missing here, and returns the replica state of every counter after the merge. 'sync' calls it on the peer",
+        output_schema = cached_schema_for_type::<MergeOutput>()
+    )]
+    async fn merge(
+        &self,
+        Parameters(args): Parameters<MergeArgs>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let principal = auth::principal(&ctx);
+        let max_counters = self.namespace().limits.max_counters;
+        self.mutate(Change::Apply("merge"), &args, |counters| {
+            let updated = merge_states(
+                counters,
+                &args.counters,
+                &self.replica,
+                principal.as_ref(),
+                max_counters,
+            )?;
+            output::success(&MergeOutput {
+                replica: self.replica.to_string(),
+                counters: replicated(counters, principal.as_ref()),
+                updated: updated.into_iter().map(|counter| counter.name).collect(),
+            })
+        })
+        .await
+    }
+
+    #[tool(
+        name = "sync",
+        description = "Tool that exchanges counters with a configured peer instance: sends the replica state of every counter the caller may read, and merges back what the peer holds, so that both converge on the sum of their changes. Rate counters are not synced",
+        output_schema = cached_schema_for_type::<SyncOutput>()
+    )]
+    async fn sync(
+        &self,
+        Parameters(args): Parameters<SyncArgs>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let peer = self
+            .peers
+            .get(&args.peer)
+            .ok_or_else(|| unknown_peer(&args.peer))?;
+        self.check_writable()?;
+        let principal = auth::principal(&ctx);
+        let namespace = self.namespace();
+        let sent = replicated(&*namespace.counters.lock().await, principal.as_ref());
+        let remote_namespace = peer.namespace.as_deref().unwrap_or(&namespace.name);
+        // The counters stay unlocked while waiting on the network; merging
+        // whatever changed meanwhile is harmless.
+        let reply =
+            tokio::time::timeout(SYNC_TIMEOUT, peer::exchange(peer, remote_namespace, &sent))
+                .await
+                .map_err(|_| sync_failed(&args.peer, "timed out"))?
+                .map_err(|error| sync_failed(&args.peer, error))?;
+        let max_counters = namespace.limits.max_counters;
+        self.mutate(Change::Apply("sync"), &args, |counters| {
+            let updated = merge_states(
+                counters,
+                &reply.counters,
+                &self.replica,
+                principal.as_ref(),
+                max_counters,
+            )?;
+            output::success(&SyncOutput {
+                peer: args.peer.clone(),
+                replica: reply.replica.clone(),
+                sent: sent.len(),
+                received: reply.counters.len(),
+                updated_on_peer: reply.updated.clone(),
+                updated,
+            })
+        })
+        .await
+    }
+
+    #[tool(
+        name = "query_audit",
+        description = "Tool that searches the audit log of tool calls, returning the most recent matches oldest first with the tool, arguments, result, duration and client of each call",
+        output_schema = cached_schema_for_type::<AuditEntries>()
+    )]
+    async fn query_audit(
+        &self,
+        Parameters(mut query): Parameters<AuditQuery>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let audit = self.audit.as_ref().ok_or_else(audit_disabled)?;
+        query.namespace = Some(self.namespace().name.clone());
+        let mut entries = audit.query(&query).map_err(|e| {
+            ErrorData::internal_error(format!("failed to read audit log: {e}"), None)
+        })?;
+        // Calls on counters the caller may not read would reveal their
+        // state.
+        let principal = auth::principal(&ctx);
+        let counters = self.namespace().counters.lock().await;
+        entries.retain(|entry| {
+            required_access(&entry.tool, entry.arguments.as_ref())
+                .iter()
+                .all(|(name, _)| {
+                    counters
+                        .get(name)
+                        .is_none_or(|c| visible(c, principal.as_ref()))
+                })
+        });
+        output::success(&AuditEntries { entries })
+    }
+}
+
+impl ServerHandler for CounterServer {
+    async fn initialize(
+        &self,
+        request: InitializeRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<InitializeResult, ErrorData> {
+        let principal = auth::principal(&context);
+        let namespace = self.resolve_namespace(&context, principal.as_ref())?;
+        let _ = self.info.namespace.set(namespace);
+        let _ = self.info.client.set(request.client_info.clone());
+        let _ = self.info.active.set(self.metrics.session_started());
+        let _ = self.info.principal.set(principal);
+        if context.peer.peer_info().is_none() {
+            context.peer.set_peer_info(request);
+        }
+        Ok(self.get_info())
+    }
+
+    fn get_info(&self) -> ServerInfo {
+        ServerInfo {
+            protocol_version: ProtocolVersion::V_2024_11_05,
+            capabilities: ServerCapabilities::builder()
+                .enable_tools()
+                .enable_resources()
+                .enable_resources_subscribe()
+                .enable_logging()
+                .build(),
+            server_info: Implementation::from_build_env(),
+            instructions: Some(
+                self.namespace()
+                    .instructions
+                    .as_deref()
+                    .unwrap_or(&self.instructions)
+                    .to_string(),
+            ),
+        }
+    }
+
+    async fn list_tools(
+        &self,
+        _pagination: Option<PaginatedRequestParam>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, ErrorData> {
+        // A tool is hidden while the caller may not use it on any counter.
+        let principal = auth::principal(&ctx);
+        let counters = self.namespace().counters.lock().await;
+        let tools = Self::tool_router()
+            .list_all()
+            .into_iter()
+            .filter(|tool| {
+                tool_permission(&tool.name).is_none_or(|permission| {
+                    counters.values().any(|counter| {
+                        counter.acl.as_ref().is_none_or(|acl| {
+                            acl.allows(Permission::Read, principal.as_ref())
+                                && acl.allows(permission, principal.as_ref())
+                        })
+                    })
+                })
+            })
+            .collect();
+        Ok(ListToolsResult {
+            tools,
+            next_cursor: None,
+        })
+    }
+
+    async fn list_resources(
+        &self,
+        _pagination: Option<PaginatedRequestParam>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, ErrorData> {
+        let principal = auth::principal(&ctx);
+        let counters = self.namespace().counters.lock().await;
+        let resources = counters
+            .iter()
+            .filter(|(_, counter)| visible(counter, principal.as_ref()))
+            .map(|(name, _)| {
+                let mut resource = RawResource::new(counter_uri(name), name.clone());
+                resource.description = Some(format!("Current state of counter '{name}'"));
+                resource.mime_type = Some(resources::MIME_TYPE.to_string());
+                resource.no_annotation()
+            })
+            .collect();
+        Ok(ListResourcesResult {
+            resources,
+            next_cursor: None,
+        })
+    }
+
+    async fn read_resource(
+        &self,
+        ReadResourceRequestParam { uri }: ReadResourceRequestParam,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, ErrorData> {
+        let name = counter_name(&uri).ok_or_else(|| invalid_uri(&uri))?;
+        let counters = self.namespace().counters.lock().await;
+        check_access(
+            &counters,
+            name,
+            Permission::Read,
+            auth::principal(&ctx).as_ref(),
+        )?;
+        let counter = counters.get(name).ok_or_else(|| counter_not_found(name))?;
+        let text = serde_json::to_string(&CounterOutput::unchanged(name, counter))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(ReadResourceResult {
+            contents: vec![ResourceContents::TextResourceContents {
+                uri: uri.clone(),
+                mime_type: Some(resources::MIME_TYPE.to_string()),
+                text,
+            }],
+        })
+    }
+
+    async fn subscribe(
+        &self,
+        SubscribeRequestParam { uri }: SubscribeRequestParam,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<(), ErrorData> {
+        let name = counter_name(&uri).ok_or_else(|| invalid_uri(&uri))?;
+        let counters = self.namespace().counters.lock().await;
+        check_access(
+            &counters,
+            name,
+            Permission::Read,
+            auth::principal(&ctx).as_ref(),
+        )?;
+        drop(counters);
+        self.namespace()
+            .subscriptions
+            .subscribe(self.session, ctx.peer, uri);
+        Ok(())
+    }
+
+    async fn unsubscribe(
+        &self,
+        UnsubscribeRequestParam { uri }: UnsubscribeRequestParam,
+        _ctx: RequestContext<RoleServer>,
+    ) -> Result<(), ErrorData> {
+        self.namespace()
+            .subscriptions
+            .unsubscribe(self.session, &uri);
+        Ok(())
+    }
+
+    async fn set_level(
+        &self,
+        SetLevelRequestParam { level }: SetLevelRequestParam,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<(), ErrorData> {
+        let namespace = self.namespace();
+        self.client_logs
+            .set_level(self.session, &namespace.name, ctx.peer, level);
+        Ok(())
+    }
+
+    async fn call_tool(
+        &self,
+        params: CallToolRequestParam,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let started = Instant::now();
+        let tool = params.name.to_string();
+        let arguments = self.audit.as_ref().and(params.arguments.clone());
+        let request_id = ctx.id.to_string();
+        let principal = auth::principal(&ctx);
+        let span = tracing::info_span!(
+            "call_tool",
+            tool,
+            { logging::SESSION_FIELD } = self.session,
+            { logging::NAMESPACE_FIELD } = self.namespace().name,
+            request_id,
+            principal = principal.as_ref().map(|p| p.0.as_str())
+        );
+        let router = Self::tool_router();
+        // Unknown names are not recorded so that clients cannot create
+        // arbitrarily many metric series.
+        let known = router.has_route(&tool);
+        let result = async {
+            self.admit(&tool, principal.as_ref())?;
+            router.call(ToolCallContext::new(self, params, ctx)).await
+        }
+        .instrument(span.clone())
+        .await;
+        let elapsed = started.elapsed();
+        let _span = span.enter();
+        let duration_ms = elapsed.as_secs_f64() * 1000.0;
+        match &result {
+            Ok(result) if result.is_error == Some(true) => {
+                tracing::info!(duration_ms, "tool reported an error")
+            }
+            Ok(_) => tracing::debug!(duration_ms, "tool call succeeded"),
+            Err(error) => tracing::info!(
+                duration_ms,
+                code = error.code.0,
+                error = %error.message,
+                "tool call rejected"
+            ),
+        }
+        if known {
+            let failed = result
+                .as_ref()
+                .map_or(true, |result| result.is_error == Some(true));
+            self.metrics.record_call(&tool, elapsed, failed);
+        }
+
+        let Some(audit) = &self.audit else {
+            return result;
+        };
+        let entry = AuditEntry {
+            timestamp: Utc::now(),
+            session: self.session,
+            namespace: self.namespace().name.clone(),
+            client: self.info.client.get().map(ClientInfo::from),
+            principal: principal.map(|p| p.0),
+            request_id,
+            tool,
+            arguments,
+            outcome: match &result {
+                Ok(result) => Outcome::Ok {
+                    is_error: result.is_error.unwrap_or(false),
+                    output: result.structured_content.clone(),
+                },
+                Err(error) => Outcome::Error {
+                    code: error.code.0,
+                    message: error.message.to_string(),
+                },
+            },
+            duration_ms,
+        };
+        // The call has already taken effect, so a failed audit write is
+        // reported but does not fail it.
+        if let Err(error) = audit.record(&entry) {
+            tracing::error!(%error, "failed to write audit entry");
+        }
+        result
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    counter::Counter,
+    idempotency::IdempotentResult,
+    wal::{Mutation, Record, WriteAheadLog},
+};
+
+/// Environment variable that overrides the directory counter state is stored in.
+pub const DATA_DIR_ENV: &str = "COUNTER_MCP_DATA_DIR";
+
+const SNAPSHOT_FILE: &str = "counters.json";
+const WAL_FILE: &str = "counters.wal";
+
+/// On-disk representation of every counter.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Sequence number of the last log record folded into this snapshot.
+    #[serde(default)]
+    pub seq: u64,
+    pub counters: BTreeMap<String, Counter>,
+    /// Results remembered for idempotency keys, by key.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub idempotency: BTreeMap<String, IdempotentResult>,
+}
+
+impl Snapshot {
+    fn apply(&mut self, record: Record) {
+        if record.seq <= self.seq {
+            return;
+        }
+        for mutation in record.mutations {
+            match mutation {
+                Mutation::Put { name, counter } => {
+                    self.counters.insert(name, counter);
+                }
+                Mutation::Delete { name } => {
+                    self.counters.remove(&name);
+                }
+                Mutation::Remember { key, entry } => {
+                    self.idempotency.insert(key, entry);
+                }
+            }
+        }
+        self.seq = record.seq;
+    }
+}
+
+/// Durable home for counter state.
+///
+/// [`CounterServer`](crate::CounterServer) loads the counters once when it is
+/// built, appends the mutations of every acknowledged tool call and
+/// periodically asks for the log to be compacted.
+pub trait Storage: Send + Sync + 'static {
+    /// Returns every persisted counter and remembered idempotent result. The
+    /// server ignores [`Snapshot::seq`].
+    fn load(&self) -> io::Result<Snapshot>;
+
+    /// Durably records `mutations` as a single atomic unit. The server only
+    /// acknowledges a tool call after this returns.
+    fn append(&self, mutations: Vec<Mutation>) -> io::Result<()>;
+
+    /// Number of records appended since the last compaction.
+    fn pending(&self) -> usize {
+        0
+    }
+
+    /// Replaces the persisted state with `counters` and `idempotency` and
+    /// drops the records they supersede. No mutation is appended while this
+    /// runs.
+    fn compact(
+        &self,
+        _counters: &BTreeMap<String, Counter>,
+        _idempotency: &BTreeMap<String, IdempotentResult>,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Stores counter state as a JSON snapshot plus a write-ahead log inside a
+/// data directory.
+///
+/// Mutations are appended to the log and fsynced before they are
+/// acknowledged. [`JsonFileStorage::compact`] folds the log into a new
+/// snapshot, which is written to a temporary file, fsynced and renamed over
+/// the previous one, so a crash leaves either the old or the new snapshot on
+/// disk and never a torn file.
+#[derive(Debug)]
+pub struct JsonFileStorage {
+    dir: PathBuf,
+    wal: Mutex<WalState>,
+}
+
+#[derive(Debug)]
+struct WalState {
+    log: WriteAheadLog,
+    seq: u64,
+}
+
+impl JsonFileStorage {
+    /// Opens the data directory, creating it if necessary, and cuts off a
+    /// log record torn by a previous crash.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let mut snapshot = read_snapshot(&dir)?;
+        let (log, records) = WriteAheadLog::open(dir.join(WAL_FILE))?;
+        for record in records {
+            snapshot.apply(record);
+        }
+
+        Ok(Self {
+            dir,
+            wal: Mutex::new(WalState {
+                log,
+                seq: snapshot.seq,
+            }),
+        })
+    }
+
+    /// Recovers the persisted state by replaying the write-ahead log on top
+    /// of the last snapshot.
+    pub fn recover(&self) -> io::Result<Snapshot> {
+        let mut snapshot = read_snapshot(&self.dir)?;
+        for record in WriteAheadLog::read(self.dir.join(WAL_FILE))? {
+            snapshot.apply(record);
+        }
+        Ok(snapshot)
+    }
+
+    /// Resolves the data directory from `COUNTER_MCP_DATA_DIR`, falling back
+    /// to the platform data directory and finally to `./data`.
+    pub fn default_dir() -> PathBuf {
+        if let Some(dir) = std::env::var_os(DATA_DIR_ENV) {
+            return PathBuf::from(dir);
+        }
+        if let Some(dir) = std::env::var_os("XDG_DATA_HOME") {
+            return Path::new(&dir).join(env!("CARGO_PKG_NAME"));
+        }
+        if let Some(home) = std::env::var_os("HOME") {
+            return Path::new(&home)
+                .join(".local/share")
+                .join(env!("CARGO_PKG_NAME"));
+        }
+        PathBuf::from("data")
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(SNAPSHOT_FILE)
+    }
+
+    fn save(&self, snapshot: &Snapshot) -> io::Result<()> {
+        let tmp = self.dir.join(format!("{SNAPSHOT_FILE}.tmp"));
+        let bytes = serde_json::to_vec_pretty(snapshot)?;
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, self.path())?;
+        // Persist the rename itself; not supported on every platform.
+        if let Ok(dir) = File::open(&self.dir) {
+            let _ = dir.sync_all();
+        }
+        Ok(())
+    }
+}
+
+fn read_snapshot(dir: &Path) -> io::Result<Snapshot> {
+    match fs::read(dir.join(SNAPSHOT_FILE)) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Snapshot::default()),
+        Err(e) => Err(e),
+    }
+}
+
+impl Storage for JsonFileStorage {
+    fn load(&self) -> io::Result<Snapshot> {
+        self.recover()
+    }
+
+    fn append(&self, mutations: Vec<Mutation>) -> io::Result<()> {
+        let mut wal = self.wal.lock().expect("wal lock poisoned");
+        // A failed append still uses up its seq, so no two records that may
+        // have reached the disk ever share one.
+        wal.seq += 1;
+        let record = Record {
+            seq: wal.seq,
+            mutations,
+        };
+        wal.log.append(&record)
+    }
+
+    fn pending(&self) -> usize {
+        self.wal.lock().expect("wal lock poisoned").log.len()
+    }
+
+    /// Writes `counters` and `idempotency` as the new snapshot, stamped with
+    /// the current log position, and empties the log.
+    fn compact(
+        &self,
+        counters: &BTreeMap<String, Counter>,
+        idempotency: &BTreeMap<String, IdempotentResult>,
+    ) -> io::Result<()> {
+        let mut wal = self.wal.lock().expect("wal lock poisoned");
+        let snapshot = Snapshot {
+            seq: wal.seq,
+            counters: counters.clone(),
+            idempotency: idempotency.clone(),
+        };
+        self.save(&snapshot)?;
+        // A crash before the reset is harmless: replay skips records the
+        // snapshot already covers.
+        wal.log.reset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter(value: i64) -> Counter {
+        Counter {
+            value,
+            ..Counter::default()
+        }
+    }
+
+    fn put(name: &str, value: i64) -> Mutation {
+        Mutation::Put {
+            name: name.to_string(),
+            counter: counter(value),
+        }
+    }
+
+    fn counters<const N: usize>(entries: [(&str, i64); N]) -> BTreeMap<String, Counter> {
+        entries
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), counter(value)))
+            .collect()
+    }
+
+    #[test]
+    fn log_is_replayed_on_top_of_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonFileStorage::open(dir.path()).unwrap();
+        storage.append(vec![put("a", 1)]).unwrap();
+        storage
+            .compact(&counters([("a", 1)]), &BTreeMap::new())
+            .unwrap();
+        storage.append(vec![put("a", 2), put("b", 7)]).unwrap();
+        storage
+            .append(vec![Mutation::Delete {
+                name: "b".to_string(),
+            }])
+            .unwrap();
+        drop(storage);
+
+        let storage = JsonFileStorage::open(dir.path()).unwrap();
+        let snapshot = storage.recover().unwrap();
+        assert_eq!(snapshot.seq, 3);
+        assert_eq!(snapshot.counters, counters([("a", 2)]));
+        assert_eq!(storage.pending(), 2);
+    }
+
+    #[test]
+    fn remembered_results_survive_replay_and_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonFileStorage::open(dir.path()).unwrap();
+        let entry = IdempotentResult {
+            tool: "increment".to_string(),
+            arguments: serde_json::json!({ "idempotency_key": "k" }),
+            result: rmcp::model::CallToolResult::success(vec![]),
+            expires_at: chrono::Utc::now(),
+        };
+        let remember = Mutation::Remember {
+            key: "k".to_string(),
+            entry: entry.clone(),
+        };
+        storage.append(vec![put("a", 1), remember]).unwrap();
+        let snapshot = storage.recover().unwrap();
+        assert_eq!(snapshot.idempotency["k"], entry);
+
+        storage
+            .compact(&snapshot.counters, &snapshot.idempotency)
+            .unwrap();
+        assert_eq!(storage.pending(), 0);
+        assert_eq!(storage.recover().unwrap().idempotency["k"], entry);
+    }
+
+    #[test]
+    fn records_covered_by_snapshot_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonFileStorage::open(dir.path()).unwrap();
+        storage.append(vec![put("a", 1)]).unwrap();
+        storage.append(vec![put("a", 2)]).unwrap();
+        // Simulate a crash after the snapshot was renamed into place but
+        // before the log was reset.
+        storage
+            .save(&Snapshot {
+                seq: 2,
+                counters: counters([("a", 2)]),
+                ..Snapshot::default()
+            })
+            .unwrap();
+        drop(storage);
+
+        let storage = JsonFileStorage::open(dir.path()).unwrap();
+        let snapshot = storage.recover().unwrap();
+        assert_eq!(snapshot.counters, counters([("a", 2)]));
+        storage.append(vec![put("a", 3)]).unwrap();
+        drop(storage);
+
+        let snapshot = JsonFileStorage::open(dir.path())
+            .unwrap()
+            .recover()
+            .unwrap();
+        assert_eq!(snapshot.seq, 3);
+        assert_eq!(snapshot.counters, counters([("a", 3)]));
+    }
+
+    #[test]
+    fn snapshot_with_bare_integers_still_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join(SNAPSHOT_FILE),
+            r#"{"counters": {"default": 7}}"#,
+        )
+        .unwrap();
+
+        let snapshot = JsonFileStorage::open(dir.path())
+            .unwrap()
+            .recover()
+            .unwrap();
+        assert_eq!(snapshot.counters, counters([("default", 7)]));
+    }
+
+    #[test]
+    fn torn_log_tail_recovers_acknowledged_mutations() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonFileStorage::open(dir.path()).unwrap();
+        for value in 1..=5 {
+            storage.append(vec![put("a", value)]).unwrap();
+        }
+        drop(storage);
+
+        let wal = dir.path().join(WAL_FILE);
+        let bytes = fs::read(&wal).unwrap();
+        fs::write(&wal, &bytes[..bytes.len() - 4]).unwrap();
+
+        let snapshot = JsonFileStorage::open(dir.path())
+            .unwrap()
+            .recover()
+            .unwrap();
+        assert_eq!(snapshot.seq, 4);
+        assert_eq!(snapshot.counters, counters([("a", 4)]));
+    }
+}
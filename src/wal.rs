@@ -0,0 +1,311 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{counter::Counter, idempotency::IdempotentResult};
+
+/// A single change to the counter registry.
+///
+/// Mutations record the resulting counter rather than the operation, so that
+/// replaying a record twice is harmless.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Mutation {
+    Put {
+        name: String,
+        #[serde(flatten)]
+        counter: Counter,
+    },
+    Delete {
+        name: String,
+    },
+    /// Remembers the result of a call made with an idempotency key.
+    Remember {
+        key: String,
+        #[serde(flatten)]
+        entry: IdempotentResult,
+    },
+}
+
+/// One acknowledged tool call. All mutations of a record are applied
+/// together or not at all.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub seq: u64,
+    pub mutations: Vec<Mutation>,
+}
+
+/// Append-only log of [`Record`]s, one JSON document per line.
+///
+/// Every append is fsynced before it returns. A record only counts once its
+/// terminating newline is on disk; a torn tail left behind by a crash is cut
+/// off when the log is reopened.
+///
+/// A failed append is cut back off the file. If even that fails the log is
+/// poisoned and refuses further appends until it is reset, so a later record
+/// never lands behind a torn line.
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    file: File,
+    /// Length of the file up to the end of the last complete record.
+    len: u64,
+    records: usize,
+    poisoned: bool,
+}
+
+impl WriteAheadLog {
+    /// Opens the log at `path`, creating it if necessary, and returns it
+    /// together with every complete record it contains.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<(Self, Vec<Record>)> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let (records, valid_len) = parse(path, &bytes)?;
+        if valid_len < bytes.len() {
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+        let wal = Self {
+            file,
+            len: valid_len as u64,
+            records: records.len(),
+            poisoned: false,
+        };
+        Ok((wal, records))
+    }
+
+    /// Reads every complete record from the log at `path` without modifying
+    /// it. A missing file reads as an empty log.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<Record>> {
+        let path = path.as_ref();
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(parse(path, &bytes)?.0),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Number of records appended since the log was last reset.
+    pub fn len(&self) -> usize {
+        self.records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+
+    pub fn append(&mut self, record: &Record) -> io::Result<()> {
+        if self.poisoned {
+            return Err(io::Error::other(
+                "the write-ahead log is poisoned by an earlier failed append",
+            ));
+        }
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let written = self
+            .file
+            .write_all(&line)
+            .and_then(|()| self.file.sync_data());
+        if let Err(e) = written {
+            // Part of the line may have reached the file; cut it off again.
+            let restored = self
+                .file
+                .set_len(self.len)
+                .and_then(|()| self.file.sync_all());
+            self.poisoned = restored.is_err();
+            return Err(e);
+        }
+        self.len += line.len() as u64;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Discards every record, typically after they were folded into a snapshot.
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.len = 0;
+        self.records = 0;
+        self.poisoned = false;
+        Ok(())
+    }
+}
+
+/// Parses complete records and returns them with the length of the valid
+/// prefix. Only the final line may be damaged; anything else is corruption
+/// that must not be papered over.
+fn parse(path: &Path, bytes: &[u8]) -> io::Result<(Vec<Record>, usize)> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while let Some(end) = bytes[offset..].iter().position(|&b| b == b'\n') {
+        let line = &bytes[offset..offset + end];
+        let next = offset + end + 1;
+        match serde_json::from_slice(line) {
+            Ok(record) => records.push(record),
+            Err(_) if next == bytes.len() => break,
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("corrupt record at byte {offset} of {}: {e}", path.display()),
+                ));
+            }
+        }
+        offset = next;
+    }
+    Ok((records, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(seq: u64, name: &str, value: i64) -> Record {
+        Record {
+            seq,
+            mutations: vec![Mutation::Put {
+                name: name.to_string(),
+                counter: Counter {
+                    value,
+                    ..Counter::default()
+                },
+            }],
+        }
+    }
+
+    #[test]
+    fn reopen_returns_appended_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.jsonl");
+        let (mut wal, records) = WriteAheadLog::open(&path).unwrap();
+        assert!(records.is_empty());
+        wal.append(&put(1, "a", 1)).unwrap();
+        wal.append(&put(2, "a", 2)).unwrap();
+        drop(wal);
+
+        let (wal, records) = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(records, vec![put(1, "a", 1), put(2, "a", 2)]);
+        assert_eq!(wal.len(), 2);
+    }
+
+    #[test]
+    fn truncated_record_is_dropped_and_log_stays_appendable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.jsonl");
+        let (mut wal, _) = WriteAheadLog::open(&path).unwrap();
+        wal.append(&put(1, "a", 1)).unwrap();
+        wal.append(&put(2, "a", 2)).unwrap();
+        drop(wal);
+
+        // Cut the second record in half, as a crash mid-write would.
+        let full = std::fs::read(&path).unwrap();
+        let first_len = full.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let cut = first_len + (full.len() - first_len) / 2;
+        std::fs::write(&path, &full[..cut]).unwrap();
+
+        let (mut wal, records) = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(records, vec![put(1, "a", 1)]);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), first_len as u64);
+
+        wal.append(&put(2, "a", 5)).unwrap();
+        drop(wal);
+        let (_, records) = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(records, vec![put(1, "a", 1), put(2, "a", 5)]);
+    }
+
+    #[test]
+    fn truncation_at_every_byte_recovers_a_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.jsonl");
+        let (mut wal, _) = WriteAheadLog::open(&path).unwrap();
+        let written: Vec<_> = (1..=3).map(|seq| put(seq, "a", seq as i64)).collect();
+        for record in &written {
+            wal.append(record).unwrap();
+        }
+        drop(wal);
+        let full = std::fs::read(&path).unwrap();
+
+        for cut in 0..=full.len() {
+            std::fs::write(&path, &full[..cut]).unwrap();
+            let (_, records) = WriteAheadLog::open(&path).unwrap();
+            let complete = full[..cut].iter().filter(|&&b| b == b'\n').count();
+            assert_eq!(records, written[..complete], "cut at byte {cut}");
+        }
+    }
+
+    #[test]
+    fn corruption_before_the_tail_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.jsonl");
+        let (mut wal, _) = WriteAheadLog::open(&path).unwrap();
+        wal.append(&put(1, "a", 1)).unwrap();
+        wal.append(&put(2, "a", 2)).unwrap();
+        drop(wal);
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[3] = b'#';
+        std::fs::write(&path, &bytes).unwrap();
+
+        let err = WriteAheadLog::open(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn records_without_counter_settings_still_parse() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.jsonl");
+        std::fs::write(
+            &path,
+            "{\"seq\":1,\"mutations\":[{\"op\":\"put\",\"name\":\"a\",\"value\":3}]}\n",
+        )
+        .unwrap();
+
+        let (_, records) = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(records, vec![put(1, "a", 3)]);
+    }
+
+    #[test]
+    fn failed_append_poisons_the_log_until_reset() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.jsonl");
+        let (mut wal, _) = WriteAheadLog::open(&path).unwrap();
+        wal.append(&put(1, "a", 1)).unwrap();
+        // A read-only handle fails both the write and the truncation after it.
+        let writable = std::mem::replace(&mut wal.file, File::open(&path).unwrap());
+
+        wal.append(&put(2, "a", 2)).unwrap_err();
+        wal.file = writable;
+        let err = wal.append(&put(3, "a", 3)).unwrap_err();
+        assert!(err.to_string().contains("poisoned"), "{err}");
+
+        wal.reset().unwrap();
+        wal.append(&put(4, "a", 4)).unwrap();
+        drop(wal);
+        let (_, records) = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(records, vec![put(4, "a", 4)]);
+    }
+
+    #[test]
+    fn reset_discards_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.jsonl");
+        let (mut wal, _) = WriteAheadLog::open(&path).unwrap();
+        wal.append(&put(1, "a", 1)).unwrap();
+        wal.reset().unwrap();
+        assert!(wal.is_empty());
+        wal.append(&put(2, "a", 2)).unwrap();
+        drop(wal);
+
+        let (_, records) = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(records, vec![put(2, "a", 2)]);
+    }
+}
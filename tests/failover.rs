@@ -0,0 +1,184 @@
+//! Replication failover between servers on localhost.
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use rmcp::{
+    RoleClient, ServiceExt,
+    model::{CallToolRequestParam, CallToolResult},
+    service::RunningService,
+    transport::StreamableHttpClientTransport,
+};
+use rust_counter_mcp::{
+    CounterServer,
+    replication::{ReplicationConfig, Role},
+};
+use serde_json::{Value, json};
+
+/// A server process, killed when dropped.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// An address on localhost that nothing listens on right now.
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn spawn(data_dir: &Path, bind: SocketAddr, args: &[&str]) -> Server {
+    let child = Command::new(env!("CARGO_BIN_EXE_rust_counter_mcp"))
+        .args(["--transport", "http", "--log-level", "warn"])
+        .arg("--bind")
+        .arg(bind.to_string())
+        .arg("--data-dir")
+        .arg(data_dir)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    Server(child)
+}
+
+/// Retries `attempt` until it returns something or `timeout` passes.
+async fn eventually<T, F: Future<Output = Option<T>>>(
+    timeout: Duration,
+    mut attempt: impl FnMut() -> F,
+) -> T {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(value) = attempt().await {
+            return value;
+        }
+        assert!(Instant::now() < deadline, "timed out");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+async fn connect(bind: SocketAddr) -> RunningService<RoleClient, ()> {
+    let url = format!("http://{bind}/mcp");
+    eventually(Duration::from_secs(10), || async {
+        let transport = StreamableHttpClientTransport::from_uri(url.as_str());
+        ().serve(transport).await.ok()
+    })
+    .await
+}
+
+async fn call(
+    client: &RunningService<RoleClient, ()>,
+    name: &str,
+    arguments: Value,
+) -> Result<CallToolResult, rmcp::ServiceError> {
+    client
+        .call_tool(CallToolRequestParam {
+            name: name.to_string().into(),
+            arguments: arguments.as_object().cloned(),
+        })
+        .await
+}
+
+fn value(result: &CallToolResult) -> i64 {
+    result.structured_content.as_ref().unwrap()["value"]
+        .as_i64()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn follower_takes_over_when_the_leader_dies() {
+    let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+    let (leader_http, leader_log) = (free_addr(), free_addr());
+    let (follower_http, follower_log) = (free_addr(), free_addr());
+    let leader_log_arg = leader_log.to_string();
+    let follower_log_arg = follower_log.to_string();
+
+    let leader = spawn(
+        dirs[0].path(),
+        leader_http,
+        &["--replication-bind", &leader_log_arg],
+    );
+    let leader_client = connect(leader_http).await;
+    let written = call(&leader_client, "increment", json!({ "amount": 5 }))
+        .await
+        .unwrap();
+    assert_eq!(value(&written), 5);
+
+    let _follower = spawn(
+        dirs[1].path(),
+        follower_http,
+        &[
+            "--follow",
+            &leader_log_arg,
+            "--replication-bind",
+            &follower_log_arg,
+            "--failover-secs",
+            "1",
+        ],
+    );
+    let follower_client = connect(follower_http).await;
+    eventually(Duration::from_secs(10), || async {
+        let result = call(&follower_client, "get_counter", json!({}))
+            .await
+            .ok()?;
+        (value(&result) == 5).then_some(())
+    })
+    .await;
+    let error = call(&follower_client, "increment", json!({}))
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("read-only"), "{error}");
+
+    drop(leader_client);
+    drop(leader);
+    let promoted = eventually(Duration::from_secs(15), || async {
+        call(&follower_client, "increment", json!({})).await.ok()
+    })
+    .await;
+    assert_eq!(value(&promoted), 6);
+}
+
+#[tokio::test]
+async fn followers_failing_over_together_leave_one_leader() {
+    let lost_leader = free_addr();
+    let logs = [free_addr(), free_addr()];
+    let followers = [
+        ("replica-a", logs[0], logs[1]),
+        ("replica-b", logs[1], logs[0]),
+    ]
+    .map(|(replica, bind, other)| {
+        CounterServer::builder()
+            .replica_id(replica)
+            .replication(ReplicationConfig {
+                role: Role::Follower,
+                bind: Some(bind),
+                leaders: vec![lost_leader, other],
+                failover_secs: Some(1),
+                ..Default::default()
+            })
+            .build()
+            .unwrap()
+    });
+    let leaders = || followers.iter().filter(|server| server.is_leader()).count();
+
+    // Both take over at the same moment; the lower replica id then steps
+    // down and follows the other.
+    eventually(Duration::from_secs(15), || async {
+        (followers[1].is_leader() && !followers[0].is_leader()).then_some(())
+    })
+    .await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(leaders(), 1);
+    assert!(followers[1].is_leader());
+}
@@ -0,0 +1,1080 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode, header},
+};
+use rmcp::{
+    ClientHandler, Peer, RoleClient, ServiceExt,
+    model::{
+        CallToolRequestParam, CallToolResult, LoggingLevel, LoggingMessageNotificationParam,
+        SetLevelRequestParam,
+    },
+    service::{NotificationContext, RunningService},
+    transport::streamable_http_server::{
+        StreamableHttpServerConfig, StreamableHttpService, session::local::LocalSessionManager,
+    },
+};
+use rust_counter_mcp::{
+    Config, CounterServer, JsonFileStorage,
+    audit::AuditLog,
+    auth::{Authenticator, hash_token, require_bearer},
+    config::{Limits, NamespaceConfig, PeerConfig},
+    logging::ClientLogs,
+    namespace::{self, NAMESPACE_HEADER},
+    rate_limit::{RateLimit, RateLimits},
+    replication::{ReplicationConfig, Role},
+};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+use tokio::sync::mpsc;
+use tower::ServiceExt as _;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Serves `server` over an in-memory pipe and connects a client to it.
+async fn connect(server: CounterServer) -> RunningService<RoleClient, ()> {
+    connect_with(server, ()).await
+}
+
+/// Like [`connect`], with `handler` receiving the server's notifications.
+async fn connect_with<H: ClientHandler>(
+    server: CounterServer,
+    handler: H,
+) -> RunningService<RoleClient, H> {
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        if let Ok(service) = server.serve(server_io).await {
+            let _ = service.waiting().await;
+        }
+    });
+    handler.serve(client_io).await.unwrap()
+}
+
+async fn call(client: &Peer<RoleClient>, name: &str, arguments: Value) -> CallToolResult {
+    client
+        .call_tool(CallToolRequestParam {
+            name: name.to_string().into(),
+            arguments: arguments.as_object().cloned(),
+        })
+        .await
+        .unwrap()
+}
+
+/// Calls a tool that is expected to fail with a JSON-RPC error.
+async fn call_err(client: &Peer<RoleClient>, name: &str, arguments: Value) -> String {
+    client
+        .call_tool(CallToolRequestParam {
+            name: name.to_string().into(),
+            arguments: arguments.as_object().cloned(),
+        })
+        .await
+        .unwrap_err()
+        .to_string()
+}
+
+fn value(result: &CallToolResult) -> i64 {
+    result.structured_content.as_ref().unwrap()["value"]
+        .as_i64()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn increment_returns_structured_counter() {
+    let client = connect(CounterServer::new()).await;
+    let result = call(&client, "increment", json!({ "amount": 5 })).await;
+    let output = result.structured_content.unwrap();
+    assert_eq!(output["name"], "default");
+    assert_eq!(output["value"], 5);
+    assert_eq!(output["previous_value"], 0);
+    assert_eq!(output["version"], 1);
+}
+
+#[tokio::test]
+async fn sessions_share_counters() {
+    let server = CounterServer::new();
+    let first = connect(server.new_session()).await;
+    let second = connect(server.new_session()).await;
+    call(&first, "increment", json!({})).await;
+    call(&second, "increment", json!({})).await;
+    assert_eq!(value(&call(&first, "get_counter", json!({})).await), 2);
+}
+
+#[tokio::test]
+async fn the_default_counter_cannot_be_deleted() {
+    let client = connect(CounterServer::new()).await;
+    let error = call_err(&client, "delete_counter", json!({ "name": "default" })).await;
+    assert!(error.contains("cannot be deleted"), "{error}");
+    assert_eq!(value(&call(&client, "increment", json!({})).await), 1);
+}
+
+#[tokio::test]
+async fn counters_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let open = || {
+        CounterServer::builder()
+            .storage(JsonFileStorage::open(dir.path()).unwrap())
+            .build()
+            .unwrap()
+    };
+
+    let client = connect(open()).await;
+    call(&client, "create_counter", json!({ "name": "tickets" })).await;
+    call(
+        &client,
+        "increment",
+        json!({ "name": "tickets", "amount": 3 }),
+    )
+    .await;
+    client.cancel().await.unwrap();
+
+    let client = connect(open()).await;
+    let result = call(&client, "get_counter", json!({ "name": "tickets" })).await;
+    assert_eq!(value(&result), 3);
+}
+
+#[tokio::test]
+async fn tool_router_can_be_mounted_elsewhere() {
+    let names: Vec<_> = CounterServer::tool_router()
+        .list_all()
+        .into_iter()
+        .map(|tool| tool.name)
+        .collect();
+    for expected in ["increment", "decrement", "get_counter", "list_counters"] {
+        assert!(
+            names.iter().any(|name| name == expected),
+            "{expected} missing"
+        );
+    }
+}
+
+#[tokio::test]
+async fn config_seeds_counters_and_caps_their_number() {
+    let config = Config::parse(
+        r#"
+        instructions = "Count tickets."
+
+        [limits]
+        max_counters = 2
+
+        [counters.tickets]
+        value = 7
+        "#,
+    )
+    .unwrap();
+    let client = connect(CounterServer::builder().config(&config).build().unwrap()).await;
+    assert_eq!(
+        client.peer_info().unwrap().instructions.as_deref(),
+        Some("Count tickets.")
+    );
+    let tickets = call(&client, "get_counter", json!({ "name": "tickets" })).await;
+    assert_eq!(value(&tickets), 7);
+
+    let error = call_err(&client, "create_counter", json!({ "name": "third" })).await;
+    assert!(
+        error.to_string().contains("maximum of 2 counters"),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn undo_and_redo_step_through_history() {
+    let client = connect(CounterServer::new()).await;
+    call(&client, "increment", json!({ "amount": 2 })).await;
+    call(&client, "set", json!({ "value": 10 })).await;
+
+    let undone = call(&client, "undo", json!({})).await;
+    let undone = undone.structured_content.unwrap();
+    assert_eq!(undone["value"], 2);
+    assert_eq!(undone["operation"]["tool"], "set");
+
+    let history = call(&client, "history", json!({})).await;
+    let history = history.structured_content.unwrap();
+    assert_eq!(history["operations"].as_array().unwrap().len(), 1);
+    assert_eq!(history["operations"][0]["tool"], "increment");
+    let client_info = history["operations"][0]["actor"]["client"]
+        .as_str()
+        .unwrap();
+    assert!(client_info.starts_with("rmcp "), "{client_info}");
+    assert_eq!(history["undone"][0]["tool"], "set");
+
+    assert_eq!(value(&call(&client, "redo", json!({})).await), 10);
+    assert_eq!(value(&call(&client, "undo", json!({})).await), 2);
+    assert_eq!(value(&call(&client, "undo", json!({})).await), 0);
+
+    // A new operation discards whatever could have been redone.
+    call(&client, "increment", json!({})).await;
+    let error = call_err(&client, "redo", json!({})).await;
+    assert!(error.to_string().contains("nothing to redo"), "{error}");
+}
+
+#[tokio::test]
+async fn tool_calls_are_audited() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = CounterServer::builder()
+        .audit(AuditLog::open(dir.path()).unwrap())
+        .build()
+        .unwrap();
+    let client = connect(server).await;
+    call(&client, "create_counter", json!({ "name": "hits" })).await;
+    call(&client, "increment", json!({ "name": "hits", "amount": 3 })).await;
+    call(&client, "increment", json!({})).await;
+
+    let result = call(
+        &client,
+        "query_audit",
+        json!({ "tool": "increment", "counter": "hits" }),
+    )
+    .await;
+    let entries = &result.structured_content.unwrap()["entries"];
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    let entry = &entries[0];
+    assert_eq!(entry["arguments"]["amount"], 3);
+    assert_eq!(entry["status"], "ok");
+    assert_eq!(entry["output"]["value"], 3);
+    assert_eq!(entry["client"]["name"], "rmcp");
+    assert!(entry["request_id"].is_string());
+    assert!(entry["duration_ms"].is_number());
+}
+
+#[tokio::test]
+async fn bounded_counters_reject_or_clamp() {
+    let client = connect(CounterServer::new()).await;
+    let seats = json!({ "name": "seats", "value": 2, "min": 0, "max": 2 });
+    call(&client, "create_counter", seats).await;
+    let stock = json!({ "name": "stock", "min": 0, "overflow": "clamp" });
+    call(&client, "create_counter", stock).await;
+
+    call(
+        &client,
+        "decrement",
+        json!({ "name": "seats", "amount": 2 }),
+    )
+    .await;
+    let error = call_err(&client, "decrement", json!({ "name": "seats" })).await;
+    assert!(
+        error.to_string().contains("would leave its bounds"),
+        "{error}"
+    );
+    let error = call_err(&client, "set", json!({ "name": "seats", "value": 3 })).await;
+    assert!(error.to_string().contains("outside the bounds"), "{error}");
+
+    let stock = call(
+        &client,
+        "decrement",
+        json!({ "name": "stock", "amount": 5 }),
+    )
+    .await;
+    assert_eq!(value(&stock), 0);
+    assert_eq!(stock.structured_content.unwrap()["min"], 0);
+}
+
+#[tokio::test]
+async fn transactions_apply_all_or_nothing() {
+    let client = connect(CounterServer::new()).await;
+    assert_eq!(
+        output_shapes(&client, "transaction").await,
+        ["TransactionOutput", "TransactionConflict"]
+    );
+    let a = json!({ "name": "a", "value": 10, "min": 0 });
+    call(&client, "create_counter", a).await;
+    call(&client, "create_counter", json!({ "name": "b" })).await;
+    let get = |name: &'static str| call(&client, "get_counter", json!({ "name": name }));
+
+    let transfer = json!({ "operations": [
+        { "op": "assert_equals", "name": "a", "value": 10 },
+        { "op": "decrement", "name": "a", "amount": 5 },
+        { "op": "increment", "name": "b", "amount": 5 },
+    ]});
+    let result = call(&client, "transaction", transfer).await;
+    let results = &result.structured_content.unwrap()["results"];
+    assert_eq!(results[1]["value"], 5);
+    assert_eq!(results[2]["value"], 5);
+
+    // Overdrawing `a` fails after `b` was credited; neither change sticks.
+    let overdraw = json!({ "operations": [
+        { "op": "increment", "name": "b", "amount": 6 },
+        { "op": "decrement", "name": "a", "amount": 6 },
+    ]});
+    let error = call_err(&client, "transaction", overdraw).await;
+    assert!(error.contains("operation 1"), "{error}");
+    assert_eq!(value(&get("b").await), 5);
+
+    let stale = json!({ "operations": [
+        { "op": "set", "name": "b", "value": 0 },
+        { "op": "assert_equals", "name": "a", "value": 10 },
+    ]});
+    let result = call(&client, "transaction", stale).await;
+    assert_eq!(result.is_error, Some(true));
+    let conflict = result.structured_content.unwrap();
+    assert_eq!(conflict["operation"], 1);
+    assert_eq!(conflict["current"]["value"], 5);
+    assert_eq!(value(&get("b").await), 5);
+    assert_eq!(get("b").await.structured_content.unwrap()["version"], 1);
+}
+
+/// Names of the shapes a tool's output schema allows.
+async fn output_shapes(client: &Peer<RoleClient>, tool: &str) -> Vec<String> {
+    let tools = client.list_all_tools().await.unwrap();
+    let tool = tools.into_iter().find(|t| t.name == tool).unwrap();
+    tool.output_schema.unwrap()["anyOf"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|shape| {
+            shape["$ref"]
+                .as_str()
+                .unwrap()
+                .replace("#/definitions/", "")
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn compare_and_set_returns_conflicts_its_schema_allows() {
+    let client = connect(CounterServer::new()).await;
+    assert_eq!(
+        output_shapes(&client, "compare_and_set").await,
+        ["CounterOutput", "Conflict"]
+    );
+    let swap = json!({ "expected_version": 0, "value": 3 });
+    assert_eq!(
+        value(&call(&client, "compare_and_set", swap.clone()).await),
+        3
+    );
+
+    let stale = call(&client, "compare_and_set", swap).await;
+    assert_eq!(stale.is_error, Some(true));
+    let conflict = stale.structured_content.unwrap();
+    assert_eq!(conflict["conflict"], true);
+    assert_eq!(conflict["current"]["value"], 3);
+}
+
+#[tokio::test]
+async fn retried_calls_replay_their_result_across_restarts() {
+    let dir = tempfile::tempdir().unwrap();
+    let open = || {
+        CounterServer::builder()
+            .storage(JsonFileStorage::open(dir.path()).unwrap())
+            .build()
+            .unwrap()
+    };
+    let retry = json!({ "amount": 5, "idempotency_key": "req-1" });
+
+    let client = connect(open()).await;
+    let first = call(&client, "increment", retry.clone()).await;
+    assert_eq!(value(&first), 5);
+    let replayed = call(&client, "increment", retry.clone()).await;
+    assert_eq!(replayed, first);
+    client.cancel().await.unwrap();
+
+    let client = connect(open()).await;
+    assert_eq!(call(&client, "increment", retry).await, first);
+    assert_eq!(value(&call(&client, "get_counter", json!({})).await), 5);
+
+    let reused = json!({ "amount": 6, "idempotency_key": "req-1" });
+    let error = call_err(&client, "increment", reused).await;
+    assert!(error.contains("already used"), "{error}");
+}
+
+#[tokio::test]
+async fn metrics_report_counters_calls_and_sessions() {
+    let server = CounterServer::new();
+    let client = connect(server.new_session()).await;
+    call(&client, "increment", json!({ "amount": 4 })).await;
+    call_err(&client, "get_counter", json!({ "name": "missing" })).await;
+
+    let text = server.render_metrics().await;
+    assert!(text.contains("counter_mcp_counter_value{namespace=\"default\",name=\"default\"} 4\n"));
+    assert!(text.contains("counter_mcp_active_sessions 1\n"));
+    assert!(text.contains("counter_mcp_tool_calls_total{tool=\"increment\"} 1\n"));
+    assert!(text.contains("counter_mcp_tool_errors_total{tool=\"get_counter\"} 1\n"));
+    assert!(text.contains("counter_mcp_tool_duration_seconds_count{tool=\"increment\"} 1\n"));
+
+    // The session ends once the server side notices the client is gone.
+    client.cancel().await.unwrap();
+    for _ in 0..100 {
+        if server
+            .render_metrics()
+            .await
+            .contains("counter_mcp_active_sessions 0\n")
+        {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("session still counted as active");
+}
+
+/// Forwards every `notifications/message` it receives.
+#[derive(Clone)]
+struct LogCollector(mpsc::UnboundedSender<LoggingMessageNotificationParam>);
+
+impl ClientHandler for LogCollector {
+    async fn on_logging_message(
+        &self,
+        params: LoggingMessageNotificationParam,
+        _context: NotificationContext<RoleClient>,
+    ) {
+        let _ = self.0.send(params);
+    }
+}
+
+#[tokio::test]
+async fn clients_receive_logs_of_their_own_calls() {
+    let logs = ClientLogs::default();
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(logs.layer()));
+    let server = CounterServer::builder().client_logs(logs).build().unwrap();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let client = connect_with(server.new_session(), LogCollector(tx)).await;
+    let (other_tx, mut other_rx) = mpsc::unbounded_channel();
+    let other = connect_with(server.new_session(), LogCollector(other_tx)).await;
+
+    for service in [client.peer(), other.peer()] {
+        service
+            .set_level(SetLevelRequestParam {
+                level: LoggingLevel::Info,
+            })
+            .await
+            .unwrap();
+    }
+    // Events outside any call or namespace are not forwarded.
+    tracing::warn!(target: "rust_counter_mcp", "listening on 127.0.0.1:7000");
+    call(&client, "increment", json!({})).await;
+    call_err(&client, "get_counter", json!({ "name": "missing" })).await;
+
+    let message = rx.recv().await.unwrap();
+    assert_eq!(message.level, LoggingLevel::Info);
+    assert_eq!(message.data["message"], "tool call rejected");
+    assert_eq!(message.data["code"], -32002);
+    // Debug-level events about the successful call were filtered out, and
+    // the other session heard nothing.
+    assert!(rx.try_recv().is_err());
+    assert!(other_rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn rate_counters_count_events_in_windows() {
+    let client = connect(CounterServer::new()).await;
+    call(
+        &client,
+        "create_counter",
+        json!({ "name": "api", "kind": "rate" }),
+    )
+    .await;
+    call(&client, "increment", json!({ "name": "api" })).await;
+    let result = call(&client, "increment", json!({ "name": "api", "amount": 3 })).await;
+    assert_eq!(value(&result), 4);
+    assert_eq!(result.structured_content.unwrap()["kind"], "rate");
+
+    let window = call(&client, "get_window", json!({ "name": "api" })).await;
+    let window = window.structured_content.unwrap();
+    assert_eq!(window["window"], "minute");
+    assert_eq!(window["events"], 4);
+    let rate = call(&client, "get_rate", json!({ "name": "api" })).await;
+    let rate = rate.structured_content.unwrap();
+    assert_eq!(rate["total"], 4);
+    assert_eq!(rate["windows"][2]["window"], "day");
+    assert_eq!(rate["windows"][2]["events"], 4);
+
+    let error = call_err(&client, "decrement", json!({ "name": "api" })).await;
+    assert!(error.contains("rate counter"), "{error}");
+    let error = call_err(&client, "get_rate", json!({})).await;
+    assert!(error.contains("not a rate counter"), "{error}");
+
+    call(&client, "reset", json!({ "name": "api" })).await;
+    let window = call(
+        &client,
+        "get_window",
+        json!({ "name": "api", "window": "day" }),
+    )
+    .await;
+    assert_eq!(window.structured_content.unwrap()["events"], 0);
+}
+
+#[tokio::test]
+async fn calls_over_the_rate_limit_or_quota_are_rejected() {
+    let server = CounterServer::builder()
+        .rate_limits(RateLimits {
+            tools: BTreeMap::from([(
+                "increment".to_string(),
+                RateLimit {
+                    burst: 2,
+                    per_minute: 1,
+                },
+            )]),
+            ..RateLimits::default()
+        })
+        .build()
+        .unwrap();
+    let client = connect(server.new_session()).await;
+    call(&client, "increment", json!({})).await;
+    call(&client, "increment", json!({})).await;
+    let error = call_err(&client, "increment", json!({})).await;
+    assert!(error.contains("-32013"), "{error}");
+    assert!(error.contains("retry after"), "{error}");
+    // Other tools and other sessions have buckets of their own.
+    assert_eq!(value(&call(&client, "get_counter", json!({})).await), 2);
+    let other = connect(server.new_session()).await;
+
+    let quota = json!({ "max": 5, "period_secs": 3600 });
+    call(
+        &other,
+        "create_counter",
+        json!({ "name": "q", "quota": quota }),
+    )
+    .await;
+    let result = call(&other, "increment", json!({ "name": "q", "amount": 4 })).await;
+    assert_eq!(result.structured_content.unwrap()["quota_used"], 4);
+    let error = call_err(&other, "increment", json!({ "name": "q", "amount": 2 })).await;
+    assert!(error.contains("-32014"), "{error}");
+    assert!(error.contains("retry_after_ms"), "{error}");
+    // Lowering gives nothing back, and raising the value any other way is
+    // charged too.
+    call(&other, "decrement", json!({ "name": "q", "amount": 3 })).await;
+    let error = call_err(&other, "set", json!({ "name": "q", "value": 3 })).await;
+    assert!(error.contains("-32014"), "{error}");
+    let result = call(&other, "set", json!({ "name": "q", "value": 2 })).await;
+    assert_eq!(result.structured_content.unwrap()["quota_used"], 5);
+    call(&other, "decrement", json!({ "name": "q" })).await;
+    let error = call_err(&other, "undo", json!({ "name": "q" })).await;
+    assert!(error.contains("-32014"), "{error}");
+}
+
+/// Posts a JSON-RPC message to `/mcp` with the bearer `token` and extra
+/// `headers`, and returns the response status, its session id and the JSON
+/// of the first SSE event it carries, if any.
+async fn post(
+    router: &axum::Router,
+    token: &str,
+    headers: &[(&str, &str)],
+    message: Value,
+) -> (StatusCode, Option<String>, Option<Value>) {
+    let mut request = Request::post("/mcp")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCEPT, "application/json, text/event-stream")
+        .header(header::AUTHORIZATION, format!("Bearer {token}"));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let request = request.body(Body::from(message.to_string())).unwrap();
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let session = response
+        .headers()
+        .get("mcp-session-id")
+        .map(|id| id.to_str().unwrap().to_string());
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let event = String::from_utf8(body.to_vec())
+        .unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix("data:"))
+        .and_then(|data| serde_json::from_str(data.trim()).ok());
+    (status, session, event)
+}
+
+/// Serves `server` over streamable HTTP at `/mcp`, behind `auth`.
+fn http_router(server: CounterServer, auth: Authenticator) -> axum::Router {
+    let service = StreamableHttpService::new(
+        move || Ok(server.new_session()),
+        LocalSessionManager::default().into(),
+        StreamableHttpServerConfig {
+            sse_keep_alive: None,
+            ..Default::default()
+        },
+    );
+    axum::Router::new()
+        .nest_service("/mcp", service)
+        .layer(axum::middleware::from_fn_with_state(auth, require_bearer))
+}
+
+fn initialize_message() -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": 0,
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": { "name": "test", "version": "1.0" },
+        },
+    })
+}
+
+/// Initializes an HTTP session with `token` and extra `headers`, and returns
+/// its id.
+async fn open_session_with(router: &axum::Router, token: &str, headers: &[(&str, &str)]) -> String {
+    let (status, session, _) = post(router, token, headers, initialize_message()).await;
+    assert_eq!(status, StatusCode::OK);
+    let session = session.unwrap();
+    let initialized = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+    post(router, token, &[("mcp-session-id", &session)], initialized).await;
+    session
+}
+
+async fn open_session(router: &axum::Router, token: &str) -> String {
+    open_session_with(router, token, &[]).await
+}
+
+/// Sends a request in an HTTP session and returns the JSON-RPC response.
+async fn request(
+    router: &axum::Router,
+    token: &str,
+    session: &str,
+    method: &str,
+    params: Value,
+) -> Value {
+    let message = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    let (_, _, response) = post(router, token, &[("mcp-session-id", session)], message).await;
+    response.unwrap()
+}
+
+#[tokio::test]
+async fn http_calls_need_a_token_and_carry_its_principal() {
+    let auth = Authenticator::new([("ci".to_string(), hash_token("s3cret"))]);
+    let router = http_router(CounterServer::new(), auth);
+
+    let (status, _, _) = post(&router, "guess", &[], initialize_message()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let session = open_session(&router, "s3cret").await;
+
+    let increment = json!({ "name": "increment", "arguments": {} });
+    let response = request(&router, "s3cret", &session, "tools/call", increment).await;
+    assert_eq!(response["result"]["structuredContent"]["value"], 1);
+    let history = json!({ "name": "history", "arguments": {} });
+    let response = request(&router, "s3cret", &session, "tools/call", history).await;
+    let operations = &response["result"]["structuredContent"]["operations"];
+    assert_eq!(operations[0]["actor"]["principal"], "ci");
+}
+
+#[tokio::test]
+async fn acls_restrict_and_hide_counters() {
+    let auth = Authenticator::new([
+        ("ops".to_string(), hash_token("ops-token")),
+        ("ci".to_string(), hash_token("ci-token")),
+    ]);
+    let router = http_router(CounterServer::new(), auth);
+    let ops = open_session(&router, "ops-token").await;
+    let ci = open_session(&router, "ci-token").await;
+    let call = |token, session, name, arguments| {
+        let router = router.clone();
+        async move {
+            let params = json!({ "name": name, "arguments": arguments });
+            request(&router, token, session, "tools/call", params).await
+        }
+    };
+
+    let seats = json!({
+        "name": "seats",
+        "acl": { "set": ["ops"], "delete": ["ops"] },
+    });
+    let response = call("ops-token", &ops, "create_counter", seats).await;
+    // The principals an ACL lists are not shown to callers.
+    assert!(response["result"]["structuredContent"]["acl"].is_null());
+    let secret = json!({ "name": "secret", "acl": { "read": ["ops"] } });
+    call("ops-token", &ops, "create_counter", secret).await;
+
+    let response = call("ci-token", &ci, "increment", json!({ "name": "seats" })).await;
+    assert_eq!(response["result"]["structuredContent"]["value"], 1);
+    let response = call("ci-token", &ci, "reset", json!({ "name": "seats" })).await;
+    assert_eq!(response["error"]["code"], -32015);
+    let operations = json!({ "operations": [
+        { "op": "increment", "name": "seats" },
+        { "op": "set", "name": "seats", "value": 7 },
+    ] });
+    let response = call("ci-token", &ci, "transaction", operations).await;
+    assert_eq!(response["error"]["code"], -32015);
+    assert_eq!(response["error"]["data"]["operation"], 1);
+    let response = call("ops-token", &ops, "reset", json!({ "name": "seats" })).await;
+    assert_eq!(response["result"]["structuredContent"]["value"], 0);
+    // A negative increment would be a decrement that ci may not make.
+    let stock = json!({ "name": "stock", "acl": { "decrement": ["ops"] } });
+    call("ops-token", &ops, "create_counter", stock).await;
+    let negative = json!({ "name": "stock", "amount": -3 });
+    let response = call("ci-token", &ci, "increment", negative).await;
+    assert_eq!(response["error"]["code"], -32602);
+    let operations = json!({ "operations": [
+        { "op": "increment", "name": "stock", "amount": -3 },
+    ] });
+    let response = call("ci-token", &ci, "transaction", operations).await;
+    assert_eq!(response["error"]["code"], -32602);
+    let response = call(
+        "ops-token",
+        &ops,
+        "delete_counter",
+        json!({ "name": "stock" }),
+    )
+    .await;
+    assert!(response["result"].is_object());
+
+    // ci cannot tell "secret" from a counter that does not exist.
+    let response = call("ci-token", &ci, "get_counter", json!({ "name": "secret" })).await;
+    assert_eq!(
+        response["error"]["message"],
+        "counter 'secret' does not exist"
+    );
+    let recreated = json!({ "name": "secret" });
+    let response = call("ci-token", &ci, "create_counter", recreated).await;
+    assert_eq!(
+        response["error"]["message"],
+        "counter name 'secret' is not available"
+    );
+    let response = call("ci-token", &ci, "list_counters", json!({})).await;
+    let names: Vec<_> = response["result"]["structuredContent"]["counters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|counter| counter["name"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["default", "seats"]);
+    let response = request(&router, "ci-token", &ci, "resources/list", json!({})).await;
+    assert_eq!(response["result"]["resources"].as_array().unwrap().len(), 2);
+    let read = json!({ "uri": "counter://secret" });
+    let response = request(&router, "ci-token", &ci, "resources/read", read).await;
+    assert!(response["error"].is_object());
+    let response = call(
+        "ops-token",
+        &ops,
+        "get_counter",
+        json!({ "name": "secret" }),
+    )
+    .await;
+    assert_eq!(response["result"]["structuredContent"]["value"], 0);
+}
+
+#[tokio::test]
+async fn namespaces_keep_their_counters_apart() {
+    let dir = tempfile::tempdir().unwrap();
+    let team_a = NamespaceConfig {
+        principals: Some(BTreeSet::from(["ci".to_string()])),
+        instructions: Some("Count team A's builds.".to_string()),
+        limits: Limits {
+            max_counters: Some(2),
+            ..Limits::default()
+        },
+        ..NamespaceConfig::default()
+    };
+    let build = || {
+        CounterServer::builder()
+            .namespace("team-a", &team_a)
+            .namespace_storage(
+                "team-a",
+                JsonFileStorage::open(namespace::data_dir(dir.path(), "team-a").unwrap()).unwrap(),
+            )
+            .build()
+            .unwrap()
+    };
+    let auth = Authenticator::new([
+        ("ci".to_string(), hash_token("ci-token")),
+        ("ops".to_string(), hash_token("ops-token")),
+    ]);
+    let router = http_router(build(), auth.clone());
+    let in_team_a = [(NAMESPACE_HEADER, "team-a")];
+    let team = open_session_with(&router, "ci-token", &in_team_a).await;
+    let default = open_session(&router, "ci-token").await;
+    let call = |session, name, arguments| {
+        let router = router.clone();
+        async move {
+            let params = json!({ "name": name, "arguments": arguments });
+            request(&router, "ci-token", session, "tools/call", params).await
+        }
+    };
+
+    let response = call(&team, "increment", json!({ "amount": 5 })).await;
+    assert_eq!(response["result"]["structuredContent"]["value"], 5);
+    let response = call(&default, "get_counter", json!({})).await;
+    assert_eq!(response["result"]["structuredContent"]["value"], 0);
+    call(&team, "create_counter", json!({ "name": "builds" })).await;
+    let response = call(&team, "create_counter", json!({ "name": "deploys" })).await;
+    assert_eq!(response["error"]["code"], -32011);
+    let response = call(&default, "get_counter", json!({ "name": "builds" })).await;
+    assert!(response["error"].is_object());
+
+    let (_, _, response) = post(&router, "ci-token", &in_team_a, initialize_message()).await;
+    assert_eq!(
+        response.unwrap()["result"]["instructions"],
+        "Count team A's builds."
+    );
+    // team-a is closed to ops, and unknown names are rejected.
+    let (status, _, response) = post(&router, "ops-token", &in_team_a, initialize_message()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        response.unwrap()["error"]["message"],
+        "namespace 'team-a' does not exist"
+    );
+    let elsewhere = [(NAMESPACE_HEADER, "team-b")];
+    let (_, _, response) = post(&router, "ci-token", &elsewhere, initialize_message()).await;
+    assert!(response.unwrap()["error"].is_object());
+
+    // The namespace is persisted in its own directory.
+    drop(router);
+    let router = http_router(build(), auth);
+    let team = open_session_with(&router, "ci-token", &in_team_a).await;
+    let params = json!({ "name": "get_counter", "arguments": {} });
+    let response = request(&router, "ci-token", &team, "tools/call", params).await;
+    assert_eq!(response["result"]["structuredContent"]["value"], 5);
+}
+
+/// Serves `server` over streamable HTTP on a free local port and returns the
+/// URL of its endpoint.
+async fn serve_on_localhost(server: CounterServer) -> String {
+    let service = StreamableHttpService::new(
+        move || Ok(server.new_session()),
+        LocalSessionManager::default().into(),
+        StreamableHttpServerConfig {
+            sse_keep_alive: None,
+            ..Default::default()
+        },
+    );
+    let router = axum::Router::new().nest_service("/mcp", service);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    format!("http://{addr}/mcp")
+}
+
+#[tokio::test]
+async fn merges_keep_counters_within_bounds() {
+    let client = connect(CounterServer::new()).await;
+    for (name, overflow) in [("strict", "error"), ("clamped", "saturate")] {
+        let counter = json!({ "name": name, "value": 8, "max": 10, "overflow": overflow });
+        call(&client, "create_counter", counter).await;
+    }
+    let state = |name: &str| {
+        json!({ "counters": { name: {
+            "max": 10,
+            "replicas": { "increments": { "peer": 5 } },
+        } } })
+    };
+
+    let error = call_err(&client, "merge", state("strict")).await;
+    assert!(error.contains("-32012"), "{error}");
+    let strict = call(&client, "get_counter", json!({ "name": "strict" })).await;
+    assert_eq!(value(&strict), 8);
+
+    let merged = call(&client, "merge", state("clamped")).await;
+    let replicas = &merged.structured_content.unwrap()["counters"]["clamped"]["replicas"];
+    // The clamp is recorded as a local decrement for the peer to take in.
+    assert_eq!(replicas["increments"]["peer"], 5);
+    assert_eq!(replicas["decrements"].as_object().unwrap().len(), 1);
+    let clamped = call(&client, "get_counter", json!({ "name": "clamped" })).await;
+    assert_eq!(value(&clamped), 10);
+}
+
+#[tokio::test]
+async fn sync_converges_two_instances() {
+    let seed = Config::parse("[counters.visits]\nvalue = 10").unwrap();
+    let remote = CounterServer::builder()
+        .config(&seed)
+        .replica_id("remote")
+        .build()
+        .unwrap();
+    let peer = PeerConfig {
+        url: serve_on_localhost(remote.clone()).await,
+        token: None,
+        namespace: None,
+    };
+    let local = CounterServer::builder()
+        .config(&seed)
+        .replica_id("local")
+        .peer("remote", peer)
+        .build()
+        .unwrap();
+    let local = connect(local).await;
+    let remote = connect(remote).await;
+
+    call(
+        &local,
+        "increment",
+        json!({ "name": "visits", "amount": 3 }),
+    )
+    .await;
+    call(&remote, "decrement", json!({ "name": "visits" })).await;
+    call(
+        &remote,
+        "create_counter",
+        json!({ "name": "builds", "value": 2 }),
+    )
+    .await;
+
+    let synced = call(&local, "sync", json!({ "peer": "remote" })).await;
+    let synced = synced.structured_content.unwrap();
+    assert_eq!(synced["replica"], "remote");
+    assert_eq!(synced["updated_on_peer"], json!(["visits"]));
+    let updated: Vec<_> = synced["updated"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|counter| counter["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(updated, ["builds", "visits"]);
+    // Both started from the same seeded value, which is counted once.
+    for client in [&local, &remote] {
+        let visits = call(client, "get_counter", json!({ "name": "visits" })).await;
+        assert_eq!(value(&visits), 12);
+    }
+    let builds = call(&local, "get_counter", json!({ "name": "builds" })).await;
+    assert_eq!(value(&builds), 2);
+
+    // Merging is idempotent: a second sync changes nothing.
+    let again = call(&local, "sync", json!({ "peer": "remote" })).await;
+    let again = again.structured_content.unwrap();
+    assert_eq!(again["updated"], json!([]));
+    assert_eq!(again["updated_on_peer"], json!([]));
+
+    let error = call_err(&local, "sync", json!({ "peer": "elsewhere" })).await;
+    assert!(error.contains("not configured"), "{error}");
+}
+
+#[tokio::test]
+async fn followers_apply_the_leaders_log_and_reject_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let leader = CounterServer::builder()
+        .replication(ReplicationConfig {
+            bind: Some("127.0.0.1:0".parse().unwrap()),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let leader_client = connect(leader.clone()).await;
+    call(
+        &leader_client,
+        "create_counter",
+        json!({ "name": "seats", "value": 3 }),
+    )
+    .await;
+
+    let follower = CounterServer::builder()
+        .storage(JsonFileStorage::open(dir.path()).unwrap())
+        .replication(ReplicationConfig {
+            role: Role::Follower,
+            leaders: vec![leader.replication_addr().unwrap()],
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    assert!(!follower.is_leader());
+    let follower_client = connect(follower.clone()).await;
+    let retried = json!({ "name": "seats", "idempotency_key": "inc-1" });
+    call(&leader_client, "increment", retried.clone()).await;
+
+    // The snapshot brings the counter over and the stream the increment.
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+    loop {
+        let result = follower_client
+            .call_tool(CallToolRequestParam {
+                name: "get_counter".into(),
+                arguments: json!({ "name": "seats" }).as_object().cloned(),
+            })
+            .await;
+        if result.as_ref().is_ok_and(|result| value(result) == 4) {
+            break;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "follower never caught up"
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let error = call_err(&follower_client, "increment", json!({ "name": "seats" })).await;
+    assert!(error.contains("read-only"), "{error}");
+    // A retry that lands on the follower gets the leader's result.
+    let replayed = call(&follower_client, "increment", retried).await;
+    assert_eq!(value(&replayed), 4);
+
+    // What the follower applied is persisted.
+    let persisted = JsonFileStorage::open(dir.path())
+        .unwrap()
+        .recover()
+        .unwrap();
+    assert_eq!(persisted.counters["seats"].value, 4);
+}
+
+/// Waits until `counter` reads `expected` through `client`.
+async fn wait_for_value(client: &Peer<RoleClient>, counter: &str, expected: i64) {
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+    loop {
+        let result = client
+            .call_tool(CallToolRequestParam {
+                name: "get_counter".into(),
+                arguments: json!({ "name": counter }).as_object().cloned(),
+            })
+            .await;
+        if result
+            .as_ref()
+            .is_ok_and(|result| value(result) == expected)
+        {
+            return;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "{counter} never reached {expected}"
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn replication_terms_fence_stale_leaders() {
+    let dir = tempfile::tempdir().unwrap();
+    let term_file = |name: &str, term: u64| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, term.to_string()).unwrap();
+        Some(path)
+    };
+    let leader = |term_file| {
+        CounterServer::builder()
+            .replication(ReplicationConfig {
+                bind: Some("127.0.0.1:0".parse().unwrap()),
+                term_file,
+                ..Default::default()
+            })
+            .build()
+            .unwrap()
+    };
+    // A leader that was replaced, and the one that replaced it in term 5.
+    let stale = leader(term_file("stale", 0));
+    let current = leader(term_file("current", 5));
+    let current_client = connect(current.clone()).await;
+    call(
+        &current_client,
+        "create_counter",
+        json!({ "name": "seats", "value": 7 }),
+    )
+    .await;
+
+    // A follower that has seen term 5 skips the stale leader, which learns
+    // of the newer term and stops accepting writes.
+    let follower = CounterServer::builder()
+        .replication(ReplicationConfig {
+            role: Role::Follower,
+            leaders: vec![
+                stale.replication_addr().unwrap(),
+                current.replication_addr().unwrap(),
+            ],
+            term_file: term_file("follower", 5),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let follower_client = connect(follower).await;
+    wait_for_value(&follower_client, "seats", 7).await;
+    assert!(!stale.is_leader());
+    let stale_client = connect(stale).await;
+    let error = call_err(&stale_client, "increment", json!({})).await;
+    assert!(error.contains("read-only"), "{error}");
+
+    // A leader restarting after it was replaced follows its successor.
+    let restarted = CounterServer::builder()
+        .replication(ReplicationConfig {
+            leaders: vec![current.replication_addr().unwrap()],
+            term_file: term_file("restarted", 0),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let restarted_client = connect(restarted.clone()).await;
+    wait_for_value(&restarted_client, "seats", 7).await;
+    assert!(!restarted.is_leader());
+    assert!(current.is_leader());
+}